@@ -1,11 +1,218 @@
 //! APIs for batch trial decryption.
 
-use alloc::vec::Vec; // module is alloc only
+use core::fmt;
+#[cfg(feature = "alloc")]
+use core::num::NonZeroUsize;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
 
+#[cfg(feature = "alloc")]
+use alloc::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    vec::Vec,
+};
+
+use crate::consts::{COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE};
+#[cfg(feature = "alloc")]
+use crate::BatchDomain;
+#[cfg(feature = "alloc")]
+use crate::{
+    check_note_validity, decrypt_compact_note_ciphertext, try_note_decryption_inner_unvalidated,
+    try_output_recovery_with_ock, wipe, CompactView, DecryptionError, RecoverableOutput,
+};
 use crate::{
-    try_compact_note_decryption_inner, try_note_decryption_inner, BatchDomain, EphemeralKeyBytes,
-    ShieldedOutput, COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE,
+    try_compact_note_decryption_inner, try_note_decryption_inner, Domain, EphemeralKeyBytes,
+    ShieldedOutput,
 };
+#[cfg(feature = "std")]
+use std::io;
+
+/// A reference to either a full or a compact shielded output.
+///
+/// This allows outputs of different concrete types, and of both kinds, to be
+/// trial-decrypted in a single batch by [`try_mixed_note_decryption`].
+pub enum MixedOutput<'a, D: Domain> {
+    /// A full output, from which the note and memo can be decrypted.
+    Full(&'a dyn ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>),
+    /// A compact output, from which only the note can be decrypted.
+    Compact(&'a dyn ShieldedOutput<D, COMPACT_NOTE_SIZE>),
+}
+
+/// A [`MixedOutput`] exposes the compact ciphertext of either kind of output.
+impl<'a, D: Domain> ShieldedOutput<D, COMPACT_NOTE_SIZE> for MixedOutput<'a, D> {
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        match self {
+            MixedOutput::Full(output) => output.ephemeral_key(),
+            MixedOutput::Compact(output) => output.ephemeral_key(),
+        }
+    }
+
+    fn cmstar_bytes(&self) -> D::ExtractedCommitmentBytes {
+        match self {
+            MixedOutput::Full(output) => output.cmstar_bytes(),
+            MixedOutput::Compact(output) => output.cmstar_bytes(),
+        }
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; COMPACT_NOTE_SIZE] {
+        match self {
+            MixedOutput::Full(output) => output.enc_ciphertext()[..COMPACT_NOTE_SIZE]
+                .try_into()
+                .unwrap(),
+            MixedOutput::Compact(output) => output.enc_ciphertext(),
+        }
+    }
+}
+
+/// The result of successfully trial-decrypting a [`MixedOutput`].
+pub enum MixedDecryption<D: Domain> {
+    /// The note, recipient, and memo decrypted from a full output.
+    Full(D::Note, D::Recipient, D::Memo),
+    /// The note and recipient decrypted from a compact output.
+    Compact(D::Note, D::Recipient),
+}
+
+#[cfg(feature = "alloc")]
+impl<D: Domain> MixedDecryption<D> {
+    /// Returns the note decrypted from a full output, which is validated separately.
+    fn unvalidated_note(&self) -> Option<&D::Note> {
+        match self {
+            MixedDecryption::Full(note, _, _) => Some(note),
+            MixedDecryption::Compact(_, _) => None,
+        }
+    }
+}
+
+/// A compact output held in an external format, such as the protobuf-derived structs
+/// of a compact block.
+///
+/// This can be implemented directly on such types, which typically store each field as
+/// a byte vector, avoiding the need to convert them into a [`ShieldedOutput`]. The field
+/// lengths are checked when the output is trial-decrypted.
+pub trait CompactOutputSource<D: Domain> {
+    /// Returns the encoding of the output's ephemeral public key.
+    fn ephemeral_key(&self) -> &[u8];
+
+    /// Returns the output's extracted note commitment, or `None` if it is malformed.
+    fn cmstar_bytes(&self) -> Option<D::ExtractedCommitmentBytes>;
+
+    /// Returns the output's compact note ciphertext.
+    fn enc_ciphertext(&self) -> &[u8];
+}
+
+/// A [`ShieldedOutput`] view of a [`CompactOutputSource`] whose field lengths have
+/// been checked.
+pub struct CompactSourceOutput<'a, D: Domain> {
+    ephemeral_key: EphemeralKeyBytes,
+    cmstar_bytes: D::ExtractedCommitmentBytes,
+    enc_ciphertext: &'a [u8; COMPACT_NOTE_SIZE],
+    truncated: bool,
+}
+
+impl<'a, D: Domain> CompactSourceOutput<'a, D> {
+    /// Returns a view of `source`, or `None` if any of its fields has the wrong length.
+    ///
+    /// The ciphertext is borrowed from `source` rather than copied.
+    pub fn new<S: CompactOutputSource<D> + ?Sized>(source: &'a S) -> Option<Self> {
+        Some(CompactSourceOutput {
+            ephemeral_key: EphemeralKeyBytes(source.ephemeral_key().try_into().ok()?),
+            cmstar_bytes: source.cmstar_bytes()?,
+            enc_ciphertext: source.enc_ciphertext().try_into().ok()?,
+            truncated: false,
+        })
+    }
+
+    /// Returns a view of `source`, truncating any oversized fields to their expected
+    /// lengths, or `None` if any of its fields is too short.
+    ///
+    /// This accepts data from sources that append padding to the canonical encodings.
+    /// Whether any field was truncated is recorded, and can be checked via
+    /// [`CompactSourceOutput::was_truncated`].
+    pub fn new_lenient<S: CompactOutputSource<D> + ?Sized>(source: &'a S) -> Option<Self> {
+        let ephemeral_key = source.ephemeral_key();
+        let enc_ciphertext = source.enc_ciphertext();
+        let truncated = ephemeral_key.len() > 32 || enc_ciphertext.len() > COMPACT_NOTE_SIZE;
+
+        Some(CompactSourceOutput {
+            ephemeral_key: EphemeralKeyBytes(ephemeral_key.get(..32)?.try_into().unwrap()),
+            cmstar_bytes: source.cmstar_bytes()?,
+            enc_ciphertext: enc_ciphertext.get(..COMPACT_NOTE_SIZE)?.try_into().unwrap(),
+            truncated,
+        })
+    }
+
+    /// Returns `true` if this view was constructed by [`CompactSourceOutput::new_lenient`]
+    /// from a source with oversized fields.
+    pub fn was_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl<'a, D: Domain> ShieldedOutput<D, COMPACT_NOTE_SIZE> for CompactSourceOutput<'a, D>
+where
+    D::ExtractedCommitmentBytes: Clone,
+{
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        self.ephemeral_key
+    }
+
+    fn cmstar_bytes(&self) -> D::ExtractedCommitmentBytes {
+        self.cmstar_bytes.clone()
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; COMPACT_NOTE_SIZE] {
+        self.enc_ciphertext
+    }
+}
+
+/// Errors that can be detected in the inputs to a batch trial decryption.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchInputError {
+    /// No incoming viewing keys were provided, so no output can be decrypted.
+    EmptyKeySet,
+    /// The `ephemeral_key` of the output at the given index is not a valid encoding of
+    /// an `EphemeralPublicKey` in that output's domain.
+    InvalidEphemeralKey(usize),
+}
+
+impl fmt::Display for BatchInputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchInputError::EmptyKeySet => write!(f, "no incoming viewing keys provided"),
+            BatchInputError::InvalidEphemeralKey(i) => {
+                write!(f, "invalid ephemeral key for output {}", i)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl std::error::Error for BatchInputError {}
+
+/// Checks the inputs to a batch trial decryption, returning an error describing the
+/// first malformed input found.
+///
+/// This is advisory only: the batch trial decryption APIs do not call it, and treat
+/// malformed inputs in the same way as outputs that are not decryptable with any of the
+/// given `ivks`. Callers that want to reject malformed inputs explicitly can call this
+/// function up front, at the cost of parsing the ephemeral key of every output a second
+/// time.
+pub fn validate_inputs<D: Domain, Output: ShieldedOutput<D, CS>, const CS: usize>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, Output)],
+) -> Result<(), BatchInputError> {
+    if ivks.is_empty() {
+        return Err(BatchInputError::EmptyKeySet);
+    }
+
+    match outputs
+        .iter()
+        .position(|(_, output)| D::epk(&output.ephemeral_key()).is_none())
+    {
+        Some(i) => Err(BatchInputError::InvalidEphemeralKey(i)),
+        None => Ok(()),
+    }
+}
 
 /// Trial decryption of a batch of notes with a set of recipients.
 ///
@@ -15,12 +222,70 @@ use crate::{
 /// with the same length and in the same order as the outputs were
 /// provided, along with the index in the `ivks` slice associated with
 /// the IVK that successfully decrypted the output.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
 #[allow(clippy::type_complexity)]
 pub fn try_note_decryption<D: BatchDomain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
     ivks: &[D::IncomingViewingKey],
     outputs: &[(D, Output)],
 ) -> Vec<Option<((D::Note, D::Recipient, D::Memo), usize)>> {
-    batch_note_decryption(ivks, outputs, try_note_decryption_inner)
+    let mut results = Vec::with_capacity(outputs.len());
+    try_note_decryption_into(ivks, outputs, &mut results);
+    results
+}
+
+/// Trial decryption of a single output with `ivks[start..]`, checking the validity of
+/// each decrypted note individually.
+#[cfg(feature = "alloc")]
+#[allow(clippy::too_many_arguments)]
+fn retry_note_decryption<D, Output, U, N, K, FR, E, const CS: usize>(
+    ivks: &[D::IncomingViewingKey],
+    domain: &D,
+    output: &Output,
+    start: usize,
+    decrypt_unvalidated: &mut U,
+    unvalidated_note: &N,
+    keep: &K,
+    cache: Option<&mut EpkCache<D>>,
+) -> Option<(FR, usize)>
+where
+    D: Domain,
+    Output: ShieldedOutput<D, CS>,
+    U: FnMut(
+        &D,
+        &D::IncomingViewingKey,
+        &EphemeralKeyBytes,
+        &Output,
+        &D::SymmetricKey,
+    ) -> Result<FR, E>,
+    N: Fn(&FR) -> Option<&D::Note>,
+    K: Fn(&FR) -> bool,
+{
+    let ephemeral_key = output.ephemeral_key();
+    let prepared;
+    let epk = match cache {
+        Some(cache) => cache.get_or_prepare(&ephemeral_key)?,
+        None => {
+            prepared = D::prepare_epk(D::epk(&ephemeral_key)?);
+            &prepared
+        }
+    };
+    ivks.iter()
+        .enumerate()
+        .skip(start)
+        .find_map(|(ivk_index, ivk)| {
+            let key = D::kdf(D::ka_agree_dec(ivk, epk), &ephemeral_key);
+            decrypt_unvalidated(domain, ivk, &ephemeral_key, output, &key)
+                .ok()
+                .filter(|res| {
+                    unvalidated_note(res).map_or(true, |note| {
+                        check_note_validity::<D>(note, &ephemeral_key, &output.cmstar_bytes())
+                            .into_result()
+                            .is_ok()
+                    }) && keep(res)
+                })
+                .map(|res| (res, ivk_index))
+        })
 }
 
 /// Trial decryption of a batch of notes for light clients with a set of recipients.
@@ -31,31 +296,167 @@ pub fn try_note_decryption<D: BatchDomain, Output: ShieldedOutput<D, ENC_CIPHERT
 /// with the same length and in the same order as the outputs were
 /// provided, along with the index in the `ivks` slice associated with
 /// the IVK that successfully decrypted the output.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
 #[allow(clippy::type_complexity)]
 pub fn try_compact_note_decryption<D: BatchDomain, Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>>(
     ivks: &[D::IncomingViewingKey],
     outputs: &[(D, Output)],
 ) -> Vec<Option<((D::Note, D::Recipient), usize)>> {
-    batch_note_decryption(ivks, outputs, try_compact_note_decryption_inner)
+    batch_note_decryption(
+        ivks,
+        outputs,
+        output_parts,
+        try_compact_note_decryption_inner,
+    )
 }
 
-fn batch_note_decryption<D: BatchDomain, Output: ShieldedOutput<D, CS>, F, FR, const CS: usize>(
+/// Trial decryption of a batch of notes with a set of recipients, appending the
+/// results to a caller-provided collection.
+///
+/// This behaves like [`try_note_decryption`], except that the results are appended to
+/// `results` (which may be any collection implementing [`Extend`]) rather than
+/// collected into a new `Vec`.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn try_note_decryption_into<D, Output, C>(
     ivks: &[D::IncomingViewingKey],
     outputs: &[(D, Output)],
-    decrypt_inner: F,
-) -> Vec<Option<(FR, usize)>>
-where
-    F: Fn(&D, &D::IncomingViewingKey, &EphemeralKeyBytes, &Output, &D::SymmetricKey) -> Option<FR>,
+    results: &mut C,
+) where
+    D: BatchDomain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+    C: Extend<Option<((D::Note, D::Recipient, D::Memo), usize)>>,
 {
+    full_note_decryption_into(
+        ivks,
+        outputs,
+        output_parts,
+        |_| true,
+        &mut Vec::new(),
+        None,
+        results,
+    )
+}
+
+/// Trial decryption of a batch of compact notes with a set of recipients, appending
+/// the results to a caller-provided collection.
+///
+/// This behaves like [`try_compact_note_decryption`], except that the results are
+/// appended to `results` (which may be any collection implementing [`Extend`]) rather
+/// than collected into a new `Vec`.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn try_compact_note_decryption_into<D, Output, C>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, Output)],
+    results: &mut C,
+) where
+    D: BatchDomain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+    C: Extend<Option<((D::Note, D::Recipient), usize)>>,
+{
+    batch_note_decryption_into(
+        ivks,
+        outputs,
+        output_parts,
+        try_compact_note_decryption_inner,
+        &mut Vec::new(),
+        None,
+        results,
+    )
+}
+
+/// A candidate (output, ivk) pair produced by [`trial_phase`].
+///
+/// With the `zeroize` feature enabled, the plaintext is wiped when the candidate is
+/// dropped. The key is only wiped if `D::SymmetricKey` does so itself on drop. Use
+/// [`TrialCandidate::into_parts`] to take ownership of the key and plaintext, in which
+/// case wiping them is the caller's responsibility.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub struct TrialCandidate<D: Domain> {
+    output_index: OutputIndex,
+    ivk_index: IvkIndex,
+    // Always `Some` until taken by `into_parts`, which consumes the candidate.
+    key: Option<D::SymmetricKey>,
+    plaintext: [u8; COMPACT_NOTE_SIZE],
+}
+
+#[cfg(feature = "alloc")]
+impl<D: Domain> TrialCandidate<D> {
+    /// Returns the index of the output in the `outputs` slice.
+    pub fn output_index(&self) -> OutputIndex {
+        self.output_index
+    }
+
+    /// Returns the index of the IVK in the `ivks` slice.
+    pub fn ivk_index(&self) -> IvkIndex {
+        self.ivk_index
+    }
+
+    /// Returns the symmetric key derived for this (output, ivk) pair.
+    pub fn key(&self) -> &D::SymmetricKey {
+        self.key.as_ref().expect("key is only taken by into_parts")
+    }
+
+    /// Returns the compact note plaintext obtained by decrypting the output with
+    /// [`TrialCandidate::key`].
+    ///
+    /// Compact ciphertexts are not authenticated, so this has not been checked in any
+    /// way.
+    pub fn plaintext(&self) -> &[u8; COMPACT_NOTE_SIZE] {
+        &self.plaintext
+    }
+
+    /// Returns the output index, IVK index, symmetric key, and compact note plaintext of
+    /// this candidate.
+    pub fn into_parts(
+        mut self,
+    ) -> (
+        OutputIndex,
+        IvkIndex,
+        D::SymmetricKey,
+        [u8; COMPACT_NOTE_SIZE],
+    ) {
+        let key = self.key.take().expect("key is only taken by into_parts");
+        (self.output_index, self.ivk_index, key, self.plaintext)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<D: Domain> Drop for TrialCandidate<D> {
+    fn drop(&mut self) {
+        wipe(&mut self.plaintext);
+    }
+}
+
+/// The first half of batch trial decryption of compact notes.
+///
+/// Derives the symmetric key for every combination of output and IVK, and decrypts
+/// the compact ciphertext with it. Outputs with an invalid ephemeral key produce no
+/// candidates. The candidates are returned ordered by output index, and then by IVK
+/// index.
+///
+/// Callers may filter, persist, or otherwise inspect the candidates before passing them
+/// to [`finalize_phase`]. Together, the two phases are equivalent to
+/// [`try_compact_note_decryption`].
+///
+/// This split is only provided for compact outputs. A full note ciphertext is
+/// authenticated as part of decryption, so there is no unchecked intermediate plaintext
+/// to hand back to the caller; use [`try_note_decryption`] instead.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn trial_phase<D: BatchDomain, Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, Output)],
+) -> Vec<TrialCandidate<D>> {
     if ivks.is_empty() {
-        return (0..outputs.len()).map(|_| None).collect();
-    };
+        return Vec::new();
+    }
 
-    // Fetch the ephemeral keys for each output, and batch-parse and prepare them.
     let ephemeral_keys = D::batch_epk(outputs.iter().map(|(_, output)| output.ephemeral_key()));
 
-    // Derive the shared secrets for all combinations of (ivk, output).
-    // The scalar multiplications cannot benefit from batching.
     let items = ephemeral_keys.iter().flat_map(|(epk, ephemeral_key)| {
         ivks.iter().map(move |ivk| {
             (
@@ -64,22 +465,2550 @@ where
             )
         })
     });
+    let mut keys = Vec::with_capacity(outputs.len() * ivks.len());
+    keys.resize_with(outputs.len() * ivks.len(), || None);
+    D::batch_kdf_into(items, &mut keys);
+
+    keys.into_iter()
+        .enumerate()
+        .filter_map(|(i, key)| {
+            let output_index = i / ivks.len();
+            let key = key?;
+            let plaintext = decrypt_compact_note_ciphertext::<D>(
+                &key,
+                outputs[output_index].1.enc_ciphertext(),
+            )?;
+            Some(TrialCandidate {
+                output_index: OutputIndex(output_index),
+                ivk_index: IvkIndex(i % ivks.len()),
+                key: Some(key),
+                plaintext,
+            })
+        })
+        .collect()
+}
+
+/// The second half of batch trial decryption of compact notes.
+///
+/// Parses the plaintext of each candidate produced by [`trial_phase`], and checks that
+/// the resulting note is valid for its output. For each output, the first candidate
+/// that succeeds is used; candidates for outputs that have already been decrypted, and
+/// candidates with out-of-range indices, are ignored.
+///
+/// Returns a vector containing the decrypted result for each output, with the same
+/// length and in the same order as the outputs were provided, along with the index in
+/// the `ivks` slice associated with the IVK that successfully decrypted the output.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn finalize_phase<D: Domain, Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, Output)],
+    candidates: impl IntoIterator<Item = TrialCandidate<D>>,
+) -> Vec<Option<((D::Note, D::Recipient), usize)>> {
+    let mut results: Vec<Option<_>> = (0..outputs.len()).map(|_| None).collect();
+    // The public fields of each output, fetched (which may require a conversion) the
+    // first time one of its candidates parses.
+    let mut fields: Vec<Option<(EphemeralKeyBytes, D::ExtractedCommitmentBytes)>> =
+        (0..outputs.len()).map(|_| None).collect();
+
+    for candidate in candidates {
+        let i = candidate.output_index.0;
+        let ((domain, output), ivk) = match (outputs.get(i), ivks.get(candidate.ivk_index.0)) {
+            (Some(output), Some(ivk)) => (output, ivk),
+            _ => continue,
+        };
+        if results[i].is_some() {
+            continue;
+        }
+
+        // Compact ciphertexts are not authenticated, so for most candidates the plaintext
+        // will fail to parse.
+        if let Some((note, to)) =
+            domain.parse_note_plaintext_without_memo_ivk(ivk, &candidate.plaintext)
+        {
+            let (ephemeral_key, cmstar_bytes) =
+                fields[i].get_or_insert_with(|| (output.ephemeral_key(), output.cmstar_bytes()));
+            if check_note_validity::<D>(&note, ephemeral_key, cmstar_bytes)
+                .into_result()
+                .is_ok()
+            {
+                results[i] = Some(((note, to), candidate.ivk_index.0));
+            }
+        }
+    }
+
+    results
+}
+
+/// Trial decryption of a batch of compact notes held in an external format with a set
+/// of recipients.
+///
+/// This behaves like [`try_compact_note_decryption`], except that the outputs are read
+/// via [`CompactOutputSource`]. Outputs with malformed fields result in `None`.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn try_compact_note_decryption_from_sources<D: BatchDomain, S: CompactOutputSource<D>>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, S)],
+) -> Vec<Option<((D::Note, D::Recipient), usize)>>
+where
+    D::ExtractedCommitmentBytes: Clone,
+{
+    let views = outputs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (domain, source))| {
+            CompactSourceOutput::new(source).map(|view| (i, domain, view))
+        })
+        .collect::<Vec<_>>();
+
+    let mut results = (0..outputs.len()).map(|_| None).collect::<Vec<_>>();
+    let decrypted = batch_note_decryption(
+        ivks,
+        &views,
+        |(_, domain, view)| (*domain, view),
+        try_compact_note_decryption_inner,
+    );
+    for ((i, _, _), result) in views.iter().zip(decrypted) {
+        results[*i] = result;
+    }
+    results
+}
+
+/// Trial decryption of a batch of full and compact notes with a set of recipients.
+///
+/// This is the batched version of [`crate::try_note_decryption`] and
+/// [`crate::try_compact_note_decryption`] for a stream of outputs containing both kinds
+/// of output, potentially of different concrete types.
+///
+/// Returns a vector containing the decrypted result for each output,
+/// with the same length and in the same order as the outputs were
+/// provided, along with the index in the `ivks` slice associated with
+/// the IVK that successfully decrypted the output.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn try_mixed_note_decryption<D: BatchDomain>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, MixedOutput<'_, D>)],
+) -> Vec<Option<(MixedDecryption<D>, usize)>> {
+    let mut results = Vec::with_capacity(outputs.len());
+    validated_note_decryption_into(
+        ivks,
+        outputs,
+        output_parts,
+        try_mixed_note_decryption_inner_unvalidated,
+        MixedDecryption::unvalidated_note,
+        |_| true,
+        &mut Vec::new(),
+        None,
+        &mut results,
+    );
+    results
+}
+
+/// Trial decryption of a batch of full outputs with a set of recipients, decrypting
+/// memos only for the most recent outputs.
+///
+/// `outputs` must be in chain order. Outputs at index `full_from` and later are
+/// trial-decrypted in full (including the memo), while earlier outputs are only
+/// trial-decrypted as compact outputs. This allows a wallet to bound the cost of
+/// scanning history while still obtaining memos for recent activity, using a single
+/// pass over a single key set.
+///
+/// Returns a vector containing the decrypted result for each output, with the same
+/// length and in the same order as the outputs were provided, along with the index in
+/// the `ivks` slice associated with the IVK that successfully decrypted the output.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn try_tiered_note_decryption<
+    D: BatchDomain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, Output)],
+    full_from: usize,
+) -> Vec<Option<(MixedDecryption<D>, usize)>> {
+    let compact = outputs[..full_from.min(outputs.len())]
+        .iter()
+        .map(|(_, output)| CompactView(output))
+        .collect::<Vec<_>>();
+    let views = outputs
+        .iter()
+        .enumerate()
+        .map(|(i, (domain, output))| match compact.get(i) {
+            Some(view) => (domain, MixedOutput::Compact(view)),
+            None => (domain, MixedOutput::Full(output)),
+        })
+        .collect::<Vec<_>>();
+
+    let mut results = Vec::with_capacity(outputs.len());
+    validated_note_decryption_into(
+        ivks,
+        &views,
+        |(domain, output)| (*domain, output),
+        try_mixed_note_decryption_inner_unvalidated,
+        MixedDecryption::unvalidated_note,
+        |_| true,
+        &mut Vec::new(),
+        None,
+        &mut results,
+    );
+    results
+}
+
+/// Decrypts a [`MixedOutput`], without checking the validity of a note decrypted from a
+/// full output.
+#[cfg(feature = "alloc")]
+fn try_mixed_note_decryption_inner_unvalidated<D: Domain>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    ephemeral_key: &EphemeralKeyBytes,
+    output: &MixedOutput<'_, D>,
+    key: &D::SymmetricKey,
+) -> Result<MixedDecryption<D>, DecryptionError> {
+    match output {
+        MixedOutput::Full(output) => {
+            try_note_decryption_inner_unvalidated(domain, ivk, *output, key)
+                .map(|(note, to, memo)| MixedDecryption::Full(note, to, memo))
+        }
+        MixedOutput::Compact(output) => {
+            try_compact_note_decryption_inner(domain, ivk, ephemeral_key, *output, key)
+                .map(|(note, to)| MixedDecryption::Compact(note, to))
+        }
+    }
+}
+
+/// Trial decryption of a batch of notes with a set of recipients, where each output is
+/// accompanied by caller-supplied metadata.
+///
+/// This is a variant of [`try_note_decryption`] for callers that need to associate each
+/// decrypted note with a record of their own (such as a transaction ID or a database row
+/// ID). The metadata `M` is not inspected; it is returned by reference alongside the
+/// result for each output that was successfully decrypted.
+///
+/// Returns a vector containing only the successfully decrypted outputs, in the same order
+/// as the outputs were provided, each with its domain (which may carry context such as
+/// the block height of the output), its metadata, and the index in the `ivks` slice
+/// associated with the IVK that successfully decrypted the output.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn try_note_decryption_with_metadata<
+    'a,
+    D: BatchDomain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+    M,
+>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &'a [(D, Output, M)],
+) -> Vec<(&'a D, &'a M, (D::Note, D::Recipient, D::Memo), IvkIndex)> {
+    let mut results = Vec::with_capacity(outputs.len());
+    full_note_decryption_into(
+        ivks,
+        outputs,
+        |(domain, output, _)| (domain, output),
+        |_| true,
+        &mut Vec::new(),
+        None,
+        &mut results,
+    );
+    with_metadata(results, outputs)
+}
+
+/// Trial decryption of a batch of notes for light clients with a set of recipients,
+/// where each output is accompanied by caller-supplied metadata.
+///
+/// This is a variant of [`try_compact_note_decryption`] for callers that need to
+/// associate each decrypted note with a record of their own (such as a transaction ID or
+/// a database row ID). The metadata `M` is not inspected; it is returned by reference
+/// alongside the result for each output that was successfully decrypted.
+///
+/// Returns a vector containing only the successfully decrypted outputs, in the same order
+/// as the outputs were provided, each with its domain (which may carry context such as
+/// the block height of the output), its metadata, and the index in the `ivks` slice
+/// associated with the IVK that successfully decrypted the output.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn try_compact_note_decryption_with_metadata<
+    'a,
+    D: BatchDomain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+    M,
+>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &'a [(D, Output, M)],
+) -> Vec<(&'a D, &'a M, (D::Note, D::Recipient), IvkIndex)> {
+    let results = batch_note_decryption(
+        ivks,
+        outputs,
+        |(domain, output, _)| (domain, output),
+        try_compact_note_decryption_inner,
+    );
+    with_metadata(results, outputs)
+}
+
+#[cfg(feature = "alloc")]
+fn with_metadata<D, Output, M, FR>(
+    results: Vec<Option<(FR, usize)>>,
+    outputs: &[(D, Output, M)],
+) -> Vec<(&D, &M, FR, IvkIndex)> {
+    results
+        .into_iter()
+        .zip(outputs.iter())
+        .filter_map(|(res, (domain, _, metadata))| {
+            res.map(|(out, i)| (domain, metadata, out, IvkIndex(i)))
+        })
+        .collect()
+}
+
+/// A tag identifying the account (or other owner) of an IVK in a [`KeySet`].
+///
+/// Tags are cloned into the results of the batch APIs that group by account, so they
+/// should be cheap to clone (for example, an account ID or an `Arc`). This trait is
+/// implemented for all types that are [`Clone`] and [`Eq`].
+pub trait IvkTag: Clone + Eq {}
+
+impl<T: Clone + Eq> IvkTag for T {}
+
+/// A set of incoming viewing keys, each associated with a tag identifying the account to
+/// which it belongs.
+///
+/// An account may have several IVKs (for example, for its external and internal
+/// scopes); these can either share a tag, or be distinguished by using a tag that
+/// includes the scope.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub struct KeySet<A, D: Domain> {
+    tags: Vec<A>,
+    ivks: Vec<D::IncomingViewingKey>,
+}
+
+#[cfg(feature = "alloc")]
+impl<A, D: Domain> Default for KeySet<A, D> {
+    fn default() -> Self {
+        KeySet {
+            tags: Vec::new(),
+            ivks: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<A: IvkTag, D: Domain> KeySet<A, D> {
+    /// Constructs an empty key set.
+    pub fn new() -> Self {
+        KeySet::default()
+    }
+
+    /// Adds the given IVK to this key set under the given tag.
+    pub fn with_key(mut self, tag: A, ivk: D::IncomingViewingKey) -> Self {
+        self.add_key(tag, ivk);
+        self
+    }
+
+    /// Adds the given IVK to this key set under the given tag.
+    pub fn add_key(&mut self, tag: A, ivk: D::IncomingViewingKey) {
+        self.tags.push(tag);
+        self.ivks.push(ivk);
+    }
+
+    /// Returns the IVKs in this key set, in the order in which they were added.
+    pub fn ivks(&self) -> &[D::IncomingViewingKey] {
+        &self.ivks
+    }
+
+    /// Returns the tag associated with the IVK at the given index in [`KeySet::ivks`].
+    pub fn tag(&self, ivk_index: IvkIndex) -> Option<&A> {
+        self.tags.get(ivk_index.0)
+    }
+
+    /// Groups batch decryption results by the tag of the IVK that decrypted them.
+    fn group<R>(&self, results: Vec<Option<(R, usize)>>) -> Vec<(A, Vec<(OutputIndex, R)>)> {
+        let mut groups: Vec<(A, Vec<(OutputIndex, R)>)> = Vec::new();
+        for tag in &self.tags {
+            if !groups.iter().any(|(t, _)| t == tag) {
+                groups.push((tag.clone(), Vec::new()));
+            }
+        }
+
+        for (output_index, (res, ivk_index)) in results
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, res)| res.map(|res| (i, res)))
+        {
+            let tag = &self.tags[ivk_index];
+            if let Some((_, hits)) = groups.iter_mut().find(|(t, _)| t == tag) {
+                hits.push((OutputIndex(output_index), res));
+            }
+        }
+
+        groups
+    }
+}
+
+/// A bounded cache of prepared ephemeral public keys, keyed by their encodings.
+///
+/// This allows the ephemeral key of an output to be parsed and prepared once, and then
+/// reused across chunks of a scan, across calls, and across retries with additional
+/// `ivk`s. [`try_note_decryption_with_config`] and
+/// [`try_compact_note_decryption_with_config`] consult the cache they are given in each
+/// of these cases, and it can also be used directly (for example with
+/// [`crate::try_note_decryption_with_prepared_epk`]). Ephemeral keys that fail to parse
+/// are cached as well, so that they are not re-parsed.
+///
+/// When the cache is full, the least recently inserted entry is evicted. Within a
+/// single chunk of a batch, the ephemeral keys of every output in the chunk are cached
+/// before any are evicted.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub struct EpkCache<D: Domain> {
+    capacity: usize,
+    entries: BTreeMap<[u8; 32], Option<D::PreparedEphemeralPublicKey>>,
+    insertion_order: VecDeque<[u8; 32]>,
+}
+
+#[cfg(feature = "alloc")]
+impl<D: Domain> EpkCache<D> {
+    /// Constructs an empty cache that holds at most `capacity` entries.
+    ///
+    /// Storage is allocated as entries are inserted, so a large `capacity` does not
+    /// reserve memory up front.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        EpkCache {
+            capacity: capacity.get(),
+            entries: BTreeMap::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    /// Returns the prepared form of `ephemeral_key`, parsing and preparing it if it is
+    /// not already cached.
+    ///
+    /// Returns `None` if `ephemeral_key` is not a valid encoding of an
+    /// `EphemeralPublicKey`.
+    pub fn get_or_prepare(
+        &mut self,
+        ephemeral_key: &EphemeralKeyBytes,
+    ) -> Option<&D::PreparedEphemeralPublicKey> {
+        if !self.entries.contains_key(&ephemeral_key.0) {
+            if self.insertion_order.len() == self.capacity {
+                if let Some(evicted) = self.insertion_order.pop_front() {
+                    self.entries.remove(&evicted);
+                }
+            }
+            self.insertion_order.push_back(ephemeral_key.0);
+            self.entries
+                .insert(ephemeral_key.0, D::epk(ephemeral_key).map(D::prepare_epk));
+        }
+
+        self.entries
+            .get(&ephemeral_key.0)
+            .and_then(|epk| epk.as_ref())
+    }
+
+    /// Returns the number of cached entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the cache contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all entries from the cache.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.insertion_order.clear();
+    }
+
+    /// Returns the cached prepared form of `ephemeral_key`, or `None` if it is not
+    /// cached or is not a valid encoding.
+    fn get(&self, ephemeral_key: &EphemeralKeyBytes) -> Option<&D::PreparedEphemeralPublicKey> {
+        self.entries
+            .get(&ephemeral_key.0)
+            .and_then(|epk| epk.as_ref())
+    }
+
+    /// Parses and prepares the given ephemeral keys that are not already cached in a
+    /// single call to [`BatchDomain::batch_epk`], and caches them without evicting any
+    /// entries.
+    fn insert_batch(&mut self, ephemeral_keys: impl Iterator<Item = EphemeralKeyBytes>)
+    where
+        D: BatchDomain,
+    {
+        let mut missing = BTreeSet::new();
+        let prepared = D::batch_epk(ephemeral_keys.filter(|ephemeral_key| {
+            !self.entries.contains_key(&ephemeral_key.0) && missing.insert(ephemeral_key.0)
+        }));
+        for (epk, ephemeral_key) in prepared {
+            self.insertion_order.push_back(ephemeral_key.0);
+            self.entries.insert(ephemeral_key.0, epk);
+        }
+    }
+
+    /// Evicts the least recently inserted entries until the cache is within capacity.
+    fn evict_excess(&mut self) {
+        while self.insertion_order.len() > self.capacity {
+            if let Some(evicted) = self.insertion_order.pop_front() {
+                self.entries.remove(&evicted);
+            }
+        }
+    }
+}
+
+/// Trial decryption of a batch of notes with the IVKs in a [`KeySet`], grouping the
+/// results by account.
+///
+/// Returns a vector containing an entry for each distinct tag in `keys`, in the order in
+/// which the tags were first added. Each entry contains the notes decrypted by the IVKs
+/// with that tag, along with the index of the output in `outputs` from which each note
+/// was decrypted.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn try_note_decryption_by_account<
+    A: IvkTag,
+    D: BatchDomain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    keys: &KeySet<A, D>,
+    outputs: &[(D, Output)],
+) -> Vec<(A, Vec<(OutputIndex, (D::Note, D::Recipient, D::Memo))>)> {
+    keys.group(try_note_decryption(keys.ivks(), outputs))
+}
+
+/// Trial decryption of a batch of notes for light clients with the IVKs in a
+/// [`KeySet`], grouping the results by account.
+///
+/// Returns a vector containing an entry for each distinct tag in `keys`, in the order in
+/// which the tags were first added. Each entry contains the notes decrypted by the IVKs
+/// with that tag, along with the index of the output in `outputs` from which each note
+/// was decrypted.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn try_compact_note_decryption_by_account<
+    A: IvkTag,
+    D: BatchDomain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    keys: &KeySet<A, D>,
+    outputs: &[(D, Output)],
+) -> Vec<(A, Vec<(OutputIndex, (D::Note, D::Recipient))>)> {
+    keys.group(try_compact_note_decryption(keys.ivks(), outputs))
+}
+
+/// The index of an output within the outputs passed to a batch trial decryption.
+///
+/// This is distinct from [`IvkIndex`], so that the index of an output and the index of
+/// the IVK that decrypted it cannot be swapped when mapping results back to a caller's
+/// records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OutputIndex(pub usize);
+
+/// The index of an IVK within the IVKs passed to a batch trial decryption.
+///
+/// This is distinct from [`OutputIndex`], so that the index of an output and the index
+/// of the IVK that decrypted it cannot be swapped when mapping results back to a
+/// caller's records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IvkIndex(pub usize);
+
+/// Implements conversions, `Display`, and offset arithmetic for an index newtype.
+///
+/// An index can be offset by a `usize` (for example, to map an index within a chunk to
+/// an index within the whole batch), and subtracting two indices of the same kind gives
+/// the distance between them. Indices of different kinds cannot be combined.
+macro_rules! impl_index {
+    ($name:ident) => {
+        impl From<usize> for $name {
+            fn from(index: usize) -> Self {
+                $name(index)
+            }
+        }
+
+        impl From<$name> for usize {
+            fn from(index: $name) -> Self {
+                index.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl Add<usize> for $name {
+            type Output = $name;
+
+            fn add(self, offset: usize) -> $name {
+                $name(self.0 + offset)
+            }
+        }
+
+        impl AddAssign<usize> for $name {
+            fn add_assign(&mut self, offset: usize) {
+                self.0 += offset;
+            }
+        }
+
+        impl Sub<usize> for $name {
+            type Output = $name;
+
+            fn sub(self, offset: usize) -> $name {
+                $name(self.0 - offset)
+            }
+        }
+
+        impl SubAssign<usize> for $name {
+            fn sub_assign(&mut self, offset: usize) {
+                self.0 -= offset;
+            }
+        }
+
+        impl Sub for $name {
+            type Output = usize;
+
+            fn sub(self, other: $name) -> usize {
+                self.0 - other.0
+            }
+        }
+    };
+}
+
+impl_index!(OutputIndex);
+impl_index!(IvkIndex);
+
+/// The results of a batch trial decryption, indexed by output.
+///
+/// This wraps the vector returned by the batch trial decryption APIs, providing
+/// O(1) lookup of the result for a given output (and the IVK that decrypted it), and
+/// iteration over the successfully decrypted outputs in output order.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub struct DecryptionResults<R> {
+    results: Vec<Option<(R, usize)>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<R> From<Vec<Option<(R, usize)>>> for DecryptionResults<R> {
+    fn from(results: Vec<Option<(R, usize)>>) -> Self {
+        DecryptionResults { results }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<R> DecryptionResults<R> {
+    /// Returns the number of outputs that were trial-decrypted.
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Returns `true` if no outputs were trial-decrypted.
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// Returns the result for the output at the given index, along with the index of
+    /// the IVK that decrypted it, or `None` if that output was not decrypted.
+    pub fn get(&self, output_index: OutputIndex) -> Option<(&R, IvkIndex)> {
+        self.results
+            .get(output_index.0)
+            .and_then(|res| res.as_ref())
+            .map(|(res, ivk_index)| (res, IvkIndex(*ivk_index)))
+    }
+
+    /// Returns the result for the output at the given index if it was decrypted by the
+    /// IVK at the given index.
+    pub fn get_for_ivk(&self, output_index: OutputIndex, ivk_index: IvkIndex) -> Option<&R> {
+        self.get(output_index)
+            .filter(|(_, i)| *i == ivk_index)
+            .map(|(res, _)| res)
+    }
+
+    /// Returns the result for the output at the given index if it was decrypted by an
+    /// IVK with the given tag in `keys`.
+    pub fn get_for_tag<A: IvkTag, D: Domain>(
+        &self,
+        output_index: OutputIndex,
+        keys: &KeySet<A, D>,
+        tag: &A,
+    ) -> Option<&R> {
+        self.get(output_index)
+            .filter(|(_, i)| keys.tag(*i) == Some(tag))
+            .map(|(res, _)| res)
+    }
+
+    /// Returns an iterator over the successfully decrypted outputs, in output order.
+    ///
+    /// Each item contains the index of the output, its result, and the index of the IVK
+    /// that decrypted it.
+    pub fn iter(&self) -> impl Iterator<Item = (OutputIndex, &R, IvkIndex)> + '_ {
+        self.results.iter().enumerate().filter_map(|(i, res)| {
+            res.as_ref()
+                .map(|(res, ivk_index)| (OutputIndex(i), res, IvkIndex(*ivk_index)))
+        })
+    }
+
+    /// Returns the underlying vector of results.
+    pub fn into_inner(self) -> Vec<Option<(R, usize)>> {
+        self.results
+    }
+}
+
+/// A note found by [`scan_outputs`].
+pub enum ScanHit<D: Domain> {
+    /// The note was received, and was decrypted with the IVK at `ivk_index`.
+    Received {
+        note: D::Note,
+        recipient: D::Recipient,
+        memo: D::Memo,
+        ivk_index: IvkIndex,
+    },
+    /// The note was sent, and was recovered with the OVK at `ovk_index`.
+    Sent {
+        note: D::Note,
+        recipient: D::Recipient,
+        memo: D::Memo,
+        ovk_index: usize,
+    },
+}
+
+/// Scans a batch of outputs for notes received by any of `ivks`, or sent by any of
+/// `ovks`, in a single pass.
+///
+/// Trial decryption with `ivks` is performed as for [`try_note_decryption`]. Outputs
+/// that are not decrypted by any of `ivks` are then recovered with `ovks`, as for
+/// [`crate::try_output_recovery_with_ovk`]. An output that was both sent and received by
+/// the caller (for example, a change output) is reported as [`ScanHit::Received`], as
+/// recovery with an OVK would produce the same note.
+///
+/// Returns a vector containing the result for each output, with the same length and in
+/// the same order as the outputs were provided.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn scan_outputs<D: BatchDomain, Output: RecoverableOutput<D>>(
+    ivks: &[D::IncomingViewingKey],
+    ovks: &[D::OutgoingViewingKey],
+    outputs: &[(D, Output)],
+) -> Vec<Option<ScanHit<D>>> {
+    try_note_decryption(ivks, outputs)
+        .into_iter()
+        .zip(outputs.iter())
+        .map(|(received, (domain, output))| match received {
+            Some(((note, recipient, memo), ivk_index)) => Some(ScanHit::Received {
+                note,
+                recipient,
+                memo,
+                ivk_index: IvkIndex(ivk_index),
+            }),
+            None if ovks.is_empty() => None,
+            None => {
+                let ephemeral_key = output.ephemeral_key();
+                let cmstar_bytes = output.cmstar_bytes();
+                ovks.iter().enumerate().find_map(|(ovk_index, ovk)| {
+                    let ock = D::derive_ock(ovk, output.cv(), &cmstar_bytes, &ephemeral_key);
+                    try_output_recovery_with_ock(domain, &ock, output, output.out_ciphertext()).map(
+                        |(note, recipient, memo)| ScanHit::Sent {
+                            note,
+                            recipient,
+                            memo,
+                            ovk_index,
+                        },
+                    )
+                })
+            }
+        })
+        .collect()
+}
+
+/// Configuration for [`try_note_decryption_with_config`],
+/// [`try_compact_note_decryption_with_config`], and (with the `std` feature)
+/// `try_compact_note_decryption_from_reader`.
+///
+/// ```
+/// use zcash_note_encryption::{batch::BatchConfig, Domain};
+///
+/// fn config<D: Domain>(is_spendable: &dyn Fn(&D::Note) -> bool) -> BatchConfig<'_, D> {
+///     BatchConfig::new()
+///         .with_chunk_size(500)
+///         .with_max_outputs(10_000)
+///         .with_note_filter(is_spendable)
+/// }
+/// ```
+#[allow(clippy::type_complexity)]
+pub struct BatchConfig<'a, D: Domain> {
+    chunk_size: usize,
+    max_outputs: Option<usize>,
+    note_filter: Option<&'a dyn Fn(&D::Note) -> bool>,
+    is_known: Option<&'a dyn Fn(&D::ExtractedCommitmentBytes) -> bool>,
+}
+
+impl<'a, D: Domain> Clone for BatchConfig<'a, D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, D: Domain> Copy for BatchConfig<'a, D> {}
+
+impl<'a, D: Domain> fmt::Debug for BatchConfig<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchConfig")
+            .field("chunk_size", &self.chunk_size)
+            .field("max_outputs", &self.max_outputs)
+            .field("note_filter", &self.note_filter.is_some())
+            .field("is_known", &self.is_known.is_some())
+            .finish()
+    }
+}
+
+impl<'a, D: Domain> Default for BatchConfig<'a, D> {
+    fn default() -> Self {
+        BatchConfig {
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+            max_outputs: None,
+            note_filter: None,
+            is_known: None,
+        }
+    }
+}
+
+impl<'a, D: Domain> BatchConfig<'a, D> {
+    /// The default maximum number of outputs trial-decrypted in a single batch.
+    pub const DEFAULT_CHUNK_SIZE: usize = 1000;
+
+    /// Constructs the default configuration.
+    pub fn new() -> Self {
+        BatchConfig::default()
+    }
+
+    /// Sets the maximum number of outputs trial-decrypted in a single batch.
+    ///
+    /// This bounds the memory used for intermediate values (such as prepared ephemeral
+    /// keys and symmetric keys), at the cost of less amortization in
+    /// [`BatchDomain::batch_epk`] and [`BatchDomain::batch_kdf`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk_size must be non-zero");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets the maximum number of outputs processed by a single call.
+    ///
+    /// Together with a [`BatchCursor`], this allows a long scan to be split across
+    /// several calls. By default, every remaining output is processed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_outputs` is zero, as the cursor would then never advance.
+    pub fn with_max_outputs(mut self, max_outputs: usize) -> Self {
+        assert!(max_outputs > 0, "max_outputs must be non-zero");
+        self.max_outputs = Some(max_outputs);
+        self
+    }
+
+    /// Sets a predicate that is applied to each note immediately after it has been
+    /// successfully decrypted and validated.
+    ///
+    /// Notes for which `filter` returns `false` (for example, notes with a value below
+    /// some dust threshold) are treated as if they had not been decrypted, so trial
+    /// decryption of the output continues with the next IVK.
+    pub fn with_note_filter(mut self, filter: &'a dyn Fn(&D::Note) -> bool) -> Self {
+        self.note_filter = Some(filter);
+        self
+    }
+
+    /// Sets a predicate identifying outputs that are already known to the caller.
+    ///
+    /// Outputs for which `is_known` returns `true` for their `cmstar_bytes` are skipped
+    /// entirely (and result in `None`). This is useful for callers that maintain an
+    /// index of their own note commitments, and so only need to trial-decrypt outputs
+    /// they have not already seen.
+    pub fn with_is_known(
+        mut self,
+        is_known: &'a dyn Fn(&D::ExtractedCommitmentBytes) -> bool,
+    ) -> Self {
+        self.is_known = Some(is_known);
+        self
+    }
+
+    /// Returns the maximum number of outputs trial-decrypted in a single batch.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Returns the maximum number of outputs processed by a single call, if any.
+    pub fn max_outputs(&self) -> Option<usize> {
+        self.max_outputs
+    }
+
+    #[cfg(feature = "alloc")]
+    fn is_known(&self, cmstar_bytes: &D::ExtractedCommitmentBytes) -> bool {
+        self.is_known
+            .map_or(false, |is_known| is_known(cmstar_bytes))
+    }
+
+    #[cfg(feature = "alloc")]
+    fn keep_note(&self, note: &D::Note) -> bool {
+        self.note_filter.map_or(true, |filter| filter(note))
+    }
+}
+
+/// Trial decryption of a batch of notes with a set of recipients, using the given
+/// configuration.
+///
+/// This behaves like [`try_note_decryption`], except that:
+/// - processing starts at the position of `cursor`, and stops after
+///   [`BatchConfig::max_outputs`] outputs (if set). On return, `cursor` has been
+///   advanced past the processed outputs, so calling this repeatedly with the same
+///   `ivks` and `outputs` until [`BatchCursor::is_complete`] returns `true` processes
+///   every output exactly once;
+/// - the outputs are processed in chunks of at most [`BatchConfig::chunk_size`];
+/// - outputs identified by [`BatchConfig::with_is_known`] are skipped;
+/// - notes rejected by [`BatchConfig::with_note_filter`] are discarded;
+/// - prepared ephemeral keys are looked up in, and added to, `cache`, so that each is
+///   parsed at most once across chunks, calls, and retries with further IVKs while it
+///   remains cached.
+///
+/// Returns a vector containing the decrypted result for each processed output, in the
+/// same order as the outputs were provided, along with the index in the `ivks` slice
+/// associated with the IVK that successfully decrypted the output. The first entry
+/// corresponds to the output at the cursor's position prior to this call.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn try_note_decryption_with_config<
+    D: BatchDomain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, Output)],
+    config: &BatchConfig<'_, D>,
+    cursor: &mut BatchCursor,
+    cache: &mut EpkCache<D>,
+) -> Vec<Option<((D::Note, D::Recipient, D::Memo), usize)>> {
+    let outputs = cursor.remaining(outputs, config.max_outputs);
+    let mut results = Vec::with_capacity(outputs.len());
+    configured_note_decryption_into(
+        outputs,
+        config,
+        Some(cache),
+        |unknown, keys, cache, decrypted| {
+            full_note_decryption_into(
+                ivks,
+                unknown,
+                |(_, (domain, output))| (domain, output),
+                |(note, _, _)| config.keep_note(note),
+                keys,
+                cache,
+                decrypted,
+            )
+        },
+        &mut results,
+    );
+    cursor.position += outputs.len();
+    results
+}
+
+/// Trial decryption of a batch of compact notes with a set of recipients, using the
+/// given configuration.
+///
+/// This behaves like [`try_compact_note_decryption`], with the same differences as
+/// [`try_note_decryption_with_config`].
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+#[allow(clippy::type_complexity)]
+pub fn try_compact_note_decryption_with_config<
+    D: BatchDomain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(D, Output)],
+    config: &BatchConfig<'_, D>,
+    cursor: &mut BatchCursor,
+    cache: &mut EpkCache<D>,
+) -> Vec<Option<((D::Note, D::Recipient), usize)>> {
+    let outputs = cursor.remaining(outputs, config.max_outputs);
+    let mut results = Vec::with_capacity(outputs.len());
+    configured_note_decryption_into(
+        outputs,
+        config,
+        Some(cache),
+        |unknown, keys, cache, decrypted| {
+            configured_compact_note_decryption_into(ivks, unknown, config, keys, cache, decrypted)
+        },
+        &mut results,
+    );
+    cursor.position += outputs.len();
+    results
+}
+
+/// The position reached by a batch trial decryption that is split across calls.
+///
+/// A `BatchCursor` records how many of the outputs passed to
+/// [`try_note_decryption_with_config`] or [`try_compact_note_decryption_with_config`]
+/// have been fully processed. Progress is recorded per call, so an interrupted call is
+/// redone from the cursor's position (the chunk it was processing is not partially
+/// skipped).
+///
+/// The cursor can be persisted via [`BatchCursor::position`] and restored with
+/// [`BatchCursor::at`], allowing a scan that was interrupted between calls to continue
+/// without redoing the outputs that were already processed. Resuming from within a
+/// single call (for example, after the key agreement stage but before the decryption
+/// stage) is not supported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchCursor {
+    position: usize,
+}
+
+impl BatchCursor {
+    /// Constructs a cursor positioned at the first output.
+    pub fn new() -> Self {
+        BatchCursor::default()
+    }
+
+    /// Constructs a cursor positioned at the given output index.
+    pub fn at(position: usize) -> Self {
+        BatchCursor { position }
+    }
+
+    /// Returns the index of the next output to be processed.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns `true` if all of the given outputs have been processed.
+    pub fn is_complete<T>(&self, outputs: &[T]) -> bool {
+        self.position >= outputs.len()
+    }
+
+    #[cfg(feature = "alloc")]
+    fn remaining<'a, T>(&self, outputs: &'a [T], max_outputs: Option<usize>) -> &'a [T] {
+        let start = self.position.min(outputs.len());
+        let end = match max_outputs {
+            Some(max_outputs) => start.saturating_add(max_outputs).min(outputs.len()),
+            None => outputs.len(),
+        };
+        &outputs[start..end]
+    }
+}
+
+/// Lazy trial decryption of a batch of notes with a set of recipients.
+///
+/// This is the lazy version of [`try_note_decryption`]. Each output is trial-decrypted
+/// only when the returned iterator is advanced, so no aggregate results are held in
+/// memory and the `alloc` feature is not required. In exchange, the batched
+/// [`BatchDomain`](crate::BatchDomain) operations are not used, and each note is
+/// validated individually as it is decrypted.
+///
+/// The iterator yields the decrypted result for each output, in the same order as the
+/// outputs were provided, along with the index in the `ivks` slice associated with the
+/// IVK that successfully decrypted the output.
+#[allow(clippy::type_complexity)]
+pub fn try_note_decryption_iter<'a, D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
+    ivks: &'a [D::IncomingViewingKey],
+    outputs: &'a [(D, Output)],
+) -> impl Iterator<Item = Option<((D::Note, D::Recipient, D::Memo), usize)>> + 'a {
+    lazy_note_decryption(ivks, outputs, output_parts, try_note_decryption_inner)
+}
+
+/// Lazy trial decryption of a batch of notes for light clients with a set of recipients.
+///
+/// This is the lazy version of [`try_compact_note_decryption`]. Each output is
+/// trial-decrypted only when the returned iterator is advanced, so no aggregate results
+/// are held in memory and the `alloc` feature is not required. In exchange, the batched
+/// [`BatchDomain`](crate::BatchDomain) operations are not used.
+///
+/// The iterator yields the decrypted result for each output, in the same order as the
+/// outputs were provided, along with the index in the `ivks` slice associated with the
+/// IVK that successfully decrypted the output.
+#[allow(clippy::type_complexity)]
+pub fn try_compact_note_decryption_iter<
+    'a,
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    ivks: &'a [D::IncomingViewingKey],
+    outputs: &'a [(D, Output)],
+) -> impl Iterator<Item = Option<((D::Note, D::Recipient), usize)>> + 'a {
+    lazy_note_decryption(
+        ivks,
+        outputs,
+        output_parts,
+        try_compact_note_decryption_inner,
+    )
+}
+
+/// The size of a record read by [`try_compact_note_decryption_from_reader`].
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub const COMPACT_RECORD_SIZE: usize = 32 + 32 + COMPACT_NOTE_SIZE;
+
+#[cfg(feature = "std")]
+struct CompactRecord<D: Domain> {
+    ephemeral_key: EphemeralKeyBytes,
+    cmstar_bytes: D::ExtractedCommitmentBytes,
+    enc_ciphertext: [u8; COMPACT_NOTE_SIZE],
+}
+
+#[cfg(feature = "std")]
+impl<D: Domain> CompactRecord<D>
+where
+    D::ExtractedCommitmentBytes: for<'a> TryFrom<&'a [u8]>,
+{
+    /// Returns `None` if `cmstar_bytes` is not a valid encoding for the domain.
+    fn parse(record: &[u8; COMPACT_RECORD_SIZE]) -> Option<Self> {
+        let (ephemeral_key, rest) = record.split_at(32);
+        let (cmstar_bytes, enc_ciphertext) = rest.split_at(32);
+        Some(CompactRecord {
+            ephemeral_key: EphemeralKeyBytes(ephemeral_key.try_into().unwrap()),
+            cmstar_bytes: D::ExtractedCommitmentBytes::try_from(cmstar_bytes).ok()?,
+            enc_ciphertext: enc_ciphertext.try_into().unwrap(),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D: Domain> ShieldedOutput<D, COMPACT_NOTE_SIZE> for CompactRecord<D>
+where
+    D::ExtractedCommitmentBytes: Clone,
+{
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        self.ephemeral_key
+    }
+
+    fn cmstar_bytes(&self) -> D::ExtractedCommitmentBytes {
+        self.cmstar_bytes.clone()
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; COMPACT_NOTE_SIZE] {
+        &self.enc_ciphertext
+    }
+}
+
+/// Reads a record into `buf`, returning `false` if `reader` is at end-of-file.
+#[cfg(feature = "std")]
+fn read_record<R: io::Read>(
+    reader: &mut R,
+    buf: &mut [u8; COMPACT_RECORD_SIZE],
+) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Trial decryption of a stream of compact outputs with a set of recipients.
+///
+/// `reader` must contain a sequence of fixed-size records, each of which is the
+/// concatenation of:
+/// - `ephemeral_key` (32 bytes);
+/// - `cmstar_bytes` (32 bytes);
+/// - the compact note ciphertext ([`COMPACT_NOTE_SIZE`] bytes).
+///
+/// This matches the Sapling and Orchard encodings, in which `cmstar_bytes` is 32 bytes.
+/// For other domains, records whose `cmstar_bytes` field cannot be converted to
+/// `D::ExtractedCommitmentBytes` are counted, but not trial-decrypted.
+///
+/// Records are read and trial-decrypted in chunks of up to [`BatchConfig::chunk_size`]
+/// records, so memory usage is bounded regardless of the length of the stream. A
+/// memory-mapped file can be scanned by passing it as a byte slice, which implements
+/// [`io::Read`]. If [`BatchConfig::max_outputs`] is set, at most that many records are
+/// read. Records identified by [`BatchConfig::with_is_known`] and notes rejected by
+/// [`BatchConfig::with_note_filter`] are skipped.
+///
+/// `on_decrypted` is called for each successfully decrypted output, in order, with the
+/// index of its record in the stream and the index in `ivks` of the IVK that decrypted
+/// it. Returns the number of records read, or an error if `reader` fails or ends
+/// partway through a record.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub fn try_compact_note_decryption_from_reader<D, R, F>(
+    domain: &D,
+    ivks: &[D::IncomingViewingKey],
+    mut reader: R,
+    config: &BatchConfig<'_, D>,
+    mut on_decrypted: F,
+) -> io::Result<u64>
+where
+    D: BatchDomain + Clone,
+    D::ExtractedCommitmentBytes: Clone + for<'a> TryFrom<&'a [u8]>,
+    R: io::Read,
+    F: FnMut(u64, D::Note, D::Recipient, IvkIndex),
+{
+    let mut remaining = config.max_outputs.unwrap_or(usize::MAX);
+    let mut buf = [0; COMPACT_RECORD_SIZE];
+    let mut chunk = Vec::with_capacity(config.chunk_size.min(remaining));
+    let mut indices = Vec::with_capacity(chunk.capacity());
+    let mut results = Vec::with_capacity(chunk.capacity());
+    let mut position = 0u64;
+    loop {
+        chunk.clear();
+        indices.clear();
+        let mut chunk_read = 0;
+        while chunk_read < config.chunk_size.min(remaining) && read_record(&mut reader, &mut buf)? {
+            if let Some(record) = CompactRecord::<D>::parse(&buf) {
+                chunk.push((domain.clone(), record));
+                indices.push(position + chunk_read as u64);
+            }
+            chunk_read += 1;
+        }
+        if chunk_read == 0 {
+            return Ok(position);
+        }
+
+        results.clear();
+        configured_note_decryption_into(
+            &chunk,
+            config,
+            None,
+            |unknown, keys, cache, decrypted| {
+                configured_compact_note_decryption_into(
+                    ivks, unknown, config, keys, cache, decrypted,
+                )
+            },
+            &mut results,
+        );
+        for (&index, result) in indices.iter().zip(results.drain(..)) {
+            if let Some(((note, recipient), ivk_idx)) = result {
+                on_decrypted(index, note, recipient, IvkIndex(ivk_idx));
+            }
+        }
+        position += chunk_read as u64;
+        remaining -= chunk_read;
+    }
+}
+
+fn output_parts<D, Output>((domain, output): &(D, Output)) -> (&D, &Output) {
+    (domain, output)
+}
+
+#[cfg(feature = "alloc")]
+fn batch_note_decryption<T, D, Output, P, F, FR, E, const CS: usize>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[T],
+    parts: P,
+    decrypt_inner: F,
+) -> Vec<Option<(FR, usize)>>
+where
+    D: BatchDomain,
+    Output: ShieldedOutput<D, CS>,
+    P: Fn(&T) -> (&D, &Output),
+    F: FnMut(
+        &D,
+        &D::IncomingViewingKey,
+        &EphemeralKeyBytes,
+        &Output,
+        &D::SymmetricKey,
+    ) -> Result<FR, E>,
+{
+    let mut results = Vec::with_capacity(outputs.len());
+    batch_note_decryption_into(
+        ivks,
+        outputs,
+        parts,
+        decrypt_inner,
+        &mut Vec::new(),
+        None,
+        &mut results,
+    );
+    results
+}
+
+#[cfg(feature = "alloc")]
+fn batch_note_decryption_into<T, D, Output, P, F, FR, E, C, const CS: usize>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[T],
+    parts: P,
+    mut decrypt_inner: F,
+    keys: &mut Vec<Option<D::SymmetricKey>>,
+    mut cache: Option<&mut EpkCache<D>>,
+    results: &mut C,
+) where
+    C: Extend<Option<(FR, usize)>>,
+    D: BatchDomain,
+    Output: ShieldedOutput<D, CS>,
+    P: Fn(&T) -> (&D, &Output),
+    F: FnMut(
+        &D,
+        &D::IncomingViewingKey,
+        &EphemeralKeyBytes,
+        &Output,
+        &D::SymmetricKey,
+    ) -> Result<FR, E>,
+{
+    if ivks.is_empty() {
+        results.extend((0..outputs.len()).map(|_| None));
+        return;
+    };
+
+    // Fetch the ephemeral keys for each output, and batch-parse and prepare those that
+    // are not already cached.
+    let prepared;
+    let ephemeral_keys: Vec<(Option<&D::PreparedEphemeralPublicKey>, EphemeralKeyBytes)> =
+        match cache.as_deref_mut() {
+            Some(cache) => {
+                let ephemeral_keys = outputs
+                    .iter()
+                    .map(|t| parts(t).1.ephemeral_key())
+                    .collect::<Vec<_>>();
+                cache.insert_batch(ephemeral_keys.iter().copied());
+                let cache = &*cache;
+                ephemeral_keys
+                    .into_iter()
+                    .map(|ephemeral_key| (cache.get(&ephemeral_key), ephemeral_key))
+                    .collect()
+            }
+            None => {
+                prepared = D::batch_epk(outputs.iter().map(|t| parts(t).1.ephemeral_key()));
+                prepared
+                    .iter()
+                    .map(|(epk, ephemeral_key)| (epk.as_ref(), *ephemeral_key))
+                    .collect()
+            }
+        };
+
+    // Derive the shared secrets for all combinations of (ivk, output).
+    // The scalar multiplications cannot benefit from batching.
+    let items = ephemeral_keys.iter().flat_map(|(epk, ephemeral_key)| {
+        ivks.iter()
+            .map(move |ivk| (epk.map(|epk| D::ka_agree_dec(ivk, epk)), ephemeral_key))
+    });
 
     // Run the batch-KDF to obtain the symmetric keys from the shared secrets.
-    let keys = D::batch_kdf(items);
+    keys.clear();
+    keys.resize_with(outputs.len() * ivks.len(), || None);
+    D::batch_kdf_into(items, keys);
 
     // Finish the trial decryption!
-    keys.chunks(ivks.len())
-        .zip(ephemeral_keys.iter().zip(outputs.iter()))
-        .map(|(key_chunk, ((_, ephemeral_key), (domain, output)))| {
-            key_chunk
+    results.extend(
+        keys.chunks(ivks.len())
+            .zip(ephemeral_keys.iter().zip(outputs.iter().map(&parts)))
+            .map(|(key_chunk, ((_, ephemeral_key), (domain, output)))| {
+                key_chunk
+                    .iter()
+                    .zip(ivks.iter().enumerate())
+                    .find_map(|(key, (i, ivk))| {
+                        key.as_ref()
+                            .and_then(|key| {
+                                decrypt_inner(domain, ivk, ephemeral_key, output, key).ok()
+                            })
+                            .map(|out| (out, i))
+                    })
+            }),
+    );
+    // This only wipes the keys if `D::SymmetricKey` does so on drop.
+    keys.clear();
+
+    if let Some(cache) = cache {
+        cache.evict_excess();
+    }
+}
+
+/// Trial decryption of a batch of full notes, checking the validity of the decrypted
+/// notes with [`BatchDomain::batch_validate_notes`].
+#[cfg(feature = "alloc")]
+fn full_note_decryption_into<T, D, Output, P, K, C>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[T],
+    parts: P,
+    keep: K,
+    keys: &mut Vec<Option<D::SymmetricKey>>,
+    cache: Option<&mut EpkCache<D>>,
+    results: &mut C,
+) where
+    C: Extend<Option<((D::Note, D::Recipient, D::Memo), usize)>>,
+    D: BatchDomain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+    P: Fn(&T) -> (&D, &Output),
+    K: Fn(&(D::Note, D::Recipient, D::Memo)) -> bool,
+{
+    validated_note_decryption_into(
+        ivks,
+        outputs,
+        parts,
+        |domain, ivk, _, output, key| {
+            try_note_decryption_inner_unvalidated(domain, ivk, output, key)
+        },
+        |(note, _, _)| Some(note),
+        keep,
+        keys,
+        cache,
+        results,
+    )
+}
+
+/// Trial-decrypts `outputs` with `decrypt_unvalidated`, and then checks the validity of
+/// every note returned by `unvalidated_note` in a single call to
+/// [`BatchDomain::batch_validate_notes`].
+///
+/// An output can decrypt to an invalid note under one IVK and to a valid note under a
+/// later one, so each output whose result is invalid or rejected by `keep` is re-tried
+/// with the IVKs that follow the one that decrypted it.
+#[cfg(feature = "alloc")]
+#[allow(clippy::too_many_arguments)]
+fn validated_note_decryption_into<T, D, Output, P, U, N, K, FR, E, C, const CS: usize>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[T],
+    parts: P,
+    mut decrypt_unvalidated: U,
+    unvalidated_note: N,
+    keep: K,
+    keys: &mut Vec<Option<D::SymmetricKey>>,
+    mut cache: Option<&mut EpkCache<D>>,
+    results: &mut C,
+) where
+    C: Extend<Option<(FR, usize)>>,
+    D: BatchDomain,
+    Output: ShieldedOutput<D, CS>,
+    P: Fn(&T) -> (&D, &Output),
+    U: FnMut(
+        &D,
+        &D::IncomingViewingKey,
+        &EphemeralKeyBytes,
+        &Output,
+        &D::SymmetricKey,
+    ) -> Result<FR, E>,
+    N: Fn(&FR) -> Option<&D::Note>,
+    K: Fn(&FR) -> bool,
+{
+    let mut decrypted = Vec::with_capacity(outputs.len());
+    batch_note_decryption_into(
+        ivks,
+        outputs,
+        &parts,
+        &mut decrypt_unvalidated,
+        keys,
+        cache.as_deref_mut(),
+        &mut decrypted,
+    );
+
+    let candidates = decrypted
+        .iter()
+        .zip(outputs.iter())
+        .enumerate()
+        .filter_map(|(i, (res, t))| {
+            let note = unvalidated_note(&res.as_ref()?.0)?;
+            let output = parts(t).1;
+            Some((i, note, output.ephemeral_key(), output.cmstar_bytes()))
+        })
+        .collect::<Vec<_>>();
+    let mut validity = D::batch_validate_notes(
+        candidates
+            .iter()
+            .map(|(_, note, ephemeral_key, cmstar_bytes)| (*note, ephemeral_key, cmstar_bytes)),
+    );
+    if validity.len() != candidates.len() {
+        // The domain's override is faulty, so fall back to checking each note.
+        validity = candidates
+            .iter()
+            .map(|(_, note, ephemeral_key, cmstar_bytes)| {
+                check_note_validity::<D>(note, ephemeral_key, cmstar_bytes)
+                    .into_result()
+                    .is_ok()
+            })
+            .collect();
+    }
+    let mut rejected = candidates
+        .iter()
+        .zip(validity)
+        .filter(|(_, valid)| !valid)
+        .map(|((i, _, _, _), _)| *i)
+        .collect::<Vec<_>>();
+    rejected.extend(
+        decrypted
+            .iter()
+            .enumerate()
+            .filter(|(_, res)| matches!(res, Some((res, _)) if !keep(res)))
+            .map(|(i, _)| i),
+    );
+    rejected.sort_unstable();
+    rejected.dedup();
+
+    for i in rejected {
+        let start = decrypted[i]
+            .take()
+            .map_or(0, |(_, ivk_index)| ivk_index + 1);
+        let (domain, output) = parts(&outputs[i]);
+        decrypted[i] = retry_note_decryption(
+            ivks,
+            domain,
+            output,
+            start,
+            &mut decrypt_unvalidated,
+            &unvalidated_note,
+            &keep,
+            cache.as_deref_mut(),
+        );
+    }
+    results.extend(decrypted);
+}
+
+/// Trial decryption of a batch of compact notes, discarding notes rejected by the
+/// configured filter.
+#[cfg(feature = "alloc")]
+#[allow(clippy::type_complexity)]
+fn configured_compact_note_decryption_into<D, Output>(
+    ivks: &[D::IncomingViewingKey],
+    outputs: &[(usize, &(D, Output))],
+    config: &BatchConfig<'_, D>,
+    keys: &mut Vec<Option<D::SymmetricKey>>,
+    cache: Option<&mut EpkCache<D>>,
+    results: &mut Vec<Option<((D::Note, D::Recipient), usize)>>,
+) where
+    D: BatchDomain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+{
+    batch_note_decryption_into(
+        ivks,
+        outputs,
+        |(_, (domain, output))| (domain, output),
+        |domain, ivk, ephemeral_key, output, key| match try_compact_note_decryption_inner(
+            domain,
+            ivk,
+            ephemeral_key,
+            output,
+            key,
+        ) {
+            Ok(res) if config.keep_note(&res.0) => Ok(res),
+            _ => Err(()),
+        },
+        keys,
+        cache,
+        results,
+    )
+}
+
+/// Trial-decrypts `outputs` in chunks of at most `config.chunk_size` with
+/// `decrypt_chunk`, skipping known outputs.
+#[cfg(feature = "alloc")]
+fn configured_note_decryption_into<D, Output, F, FR, const CS: usize>(
+    outputs: &[(D, Output)],
+    config: &BatchConfig<'_, D>,
+    mut cache: Option<&mut EpkCache<D>>,
+    mut decrypt_chunk: F,
+    results: &mut Vec<Option<(FR, usize)>>,
+) where
+    D: BatchDomain,
+    Output: ShieldedOutput<D, CS>,
+    F: FnMut(
+        &[(usize, &(D, Output))],
+        &mut Vec<Option<D::SymmetricKey>>,
+        Option<&mut EpkCache<D>>,
+        &mut Vec<Option<(FR, usize)>>,
+    ),
+{
+    let mut decrypted = Vec::with_capacity(config.chunk_size.min(outputs.len()));
+    // Reused across chunks, so that `BatchDomain::batch_kdf_into` does not need to
+    // allocate for each chunk.
+    let mut keys = Vec::new();
+    for chunk in outputs.chunks(config.chunk_size) {
+        let unknown = chunk
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, output))| !config.is_known(&output.cmstar_bytes()))
+            .collect::<Vec<_>>();
+
+        decrypted.clear();
+        decrypt_chunk(&unknown, &mut keys, cache.as_deref_mut(), &mut decrypted);
+
+        let start = results.len();
+        results.extend(chunk.iter().map(|_| None));
+        for ((i, _), result) in unknown.iter().zip(decrypted.drain(..)) {
+            results[start + i] = result;
+        }
+    }
+}
+
+fn lazy_note_decryption<'a, T, D, Output, P, F, FR, E, const CS: usize>(
+    ivks: &'a [D::IncomingViewingKey],
+    outputs: &'a [T],
+    parts: P,
+    mut decrypt_inner: F,
+) -> impl Iterator<Item = Option<(FR, usize)>> + 'a
+where
+    D: Domain + 'a,
+    Output: ShieldedOutput<D, CS> + 'a,
+    P: Fn(&T) -> (&D, &Output) + 'a,
+    F: FnMut(
+            &D,
+            &D::IncomingViewingKey,
+            &EphemeralKeyBytes,
+            &Output,
+            &D::SymmetricKey,
+        ) -> Result<FR, E>
+        + 'a,
+{
+    outputs.iter().map(move |t| {
+        let (domain, output) = parts(t);
+        let ephemeral_key = output.ephemeral_key();
+        let epk = D::prepare_epk(D::epk(&ephemeral_key)?);
+
+        ivks.iter().enumerate().find_map(|(i, ivk)| {
+            let key = D::kdf(D::ka_agree_dec(ivk, &epk), &ephemeral_key);
+            decrypt_inner(domain, ivk, &ephemeral_key, output, &key)
+                .ok()
+                .map(|out| (out, i))
+        })
+    })
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use alloc::{format, vec, vec::Vec};
+    use core::num::NonZeroUsize;
+
+    use super::{
+        finalize_phase, scan_outputs, trial_phase, try_compact_note_decryption,
+        try_compact_note_decryption_from_sources, try_compact_note_decryption_into,
+        try_compact_note_decryption_iter, try_compact_note_decryption_with_metadata,
+        try_mixed_note_decryption, try_note_decryption, try_note_decryption_by_account,
+        try_note_decryption_into, try_note_decryption_iter, try_note_decryption_with_config,
+        try_note_decryption_with_metadata, try_tiered_note_decryption, validate_inputs,
+        BatchConfig, BatchCursor, BatchInputError, CompactOutputSource, CompactSourceOutput,
+        DecryptionResults, EpkCache, IvkIndex, KeySet, MixedDecryption, MixedOutput, OutputIndex,
+        ScanHit, TrialCandidate,
+    };
+    use crate::consts::COMPACT_NOTE_SIZE;
+    use crate::test_domain::{
+        compact_output, full_output, sent_output, MockCmx, MockDomain, MockNote, MockOutput,
+        BATCH_FAULTY_EK, BATCH_REJECTED_EK, INVALID_EPK,
+    };
+    use crate::{EphemeralKeyBytes, ShieldedOutput};
+
+    #[cfg(feature = "std")]
+    use super::{try_compact_note_decryption_from_reader, COMPACT_RECORD_SIZE};
+
+    fn epk_cache(capacity: usize) -> EpkCache<MockDomain> {
+        EpkCache::new(NonZeroUsize::new(capacity).unwrap())
+    }
+
+    #[test]
+    fn invalid_note_falls_through_to_next_ivk() {
+        // Both IVKs can parse the note plaintext, but only the note parsed by IVK 1
+        // matches the output's commitment.
+        let outputs = [
+            (MockDomain, full_output(1, 0b11, 7, MockCmx::for_note(1, 7))),
+            (MockDomain, full_output(2, 0b01, 8, MockCmx::for_note(1, 8))),
+        ];
+
+        let results = try_note_decryption(&[0, 1], &outputs);
+        assert_eq!(
+            results[0],
+            Some(((MockNote { ivk: 1, value: 7 }, 1, ()), 1))
+        );
+        assert_eq!(results[1], None);
+    }
+
+    #[test]
+    fn batch_rejected_note_is_retried_with_later_ivks() {
+        // The batched validity check rejects the notes from the first and last outputs.
+        // Each is re-tried starting from the IVK after the one that decrypted it, so with
+        // a repeated IVK the non-batched check accepts the note at index 1, and with an
+        // IVK that cannot parse the note the output is not decrypted.
+        let outputs = [
+            (
+                MockDomain,
+                full_output(BATCH_REJECTED_EK, 0b01, 7, MockCmx::for_note(0, 7)),
+            ),
+            (MockDomain, full_output(1, 0b01, 8, MockCmx::for_note(0, 8))),
+            (
+                MockDomain,
+                full_output(BATCH_REJECTED_EK, 0b01, 9, MockCmx::for_note(0, 9)),
+            ),
+        ];
+
+        let results = try_note_decryption(&[0, 0], &outputs);
+        assert_eq!(
+            results[0],
+            Some(((MockNote { ivk: 0, value: 7 }, 0, ()), 1))
+        );
+        assert_eq!(
+            results[1],
+            Some(((MockNote { ivk: 0, value: 8 }, 0, ()), 0))
+        );
+        assert_eq!(
+            results[2],
+            Some(((MockNote { ivk: 0, value: 9 }, 0, ()), 1))
+        );
+
+        let results = try_note_decryption(&[0, 1], &outputs);
+        assert_eq!(results[0], None);
+        assert_eq!(results[2], None);
+    }
+
+    #[test]
+    fn batch_validation_applies_to_every_full_decryption_path() {
+        // IVK 1 cannot parse the note, so each output is only decrypted if the batched
+        // validity check accepts the note decrypted by IVK 0.
+        let rejected = full_output(BATCH_REJECTED_EK, 0b01, 7, MockCmx::for_note(0, 7));
+        let accepted = full_output(1, 0b01, 8, MockCmx::for_note(0, 8));
+        let ivks = [0, 1];
+        let expected = vec![None, Some(((MockNote { ivk: 0, value: 8 }, 0, ()), 0))];
+
+        let outputs = [
+            (MockDomain, rejected.clone()),
+            (MockDomain, accepted.clone()),
+        ];
+        let mut results = Vec::new();
+        try_note_decryption_into(&ivks, &outputs, &mut results);
+        assert_eq!(results, expected);
+
+        let results = try_note_decryption_with_config(
+            &ivks,
+            &outputs,
+            &BatchConfig::new(),
+            &mut BatchCursor::new(),
+            &mut epk_cache(2),
+        );
+        assert_eq!(results, expected);
+
+        let with_metadata = [
+            (MockDomain, rejected.clone(), "a"),
+            (MockDomain, accepted.clone(), "b"),
+        ];
+        let results = try_note_decryption_with_metadata(&ivks, &with_metadata);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].1, &"b");
+
+        let mixed = [
+            (MockDomain, MixedOutput::Full(&rejected)),
+            (MockDomain, MixedOutput::Full(&accepted)),
+        ];
+        let results = try_mixed_note_decryption(&ivks, &mixed);
+        assert!(results[0].is_none());
+        assert!(matches!(
+            results[1],
+            Some((
+                MixedDecryption::Full(MockNote { ivk: 0, value: 8 }, 0, ()),
+                0
+            ))
+        ));
+    }
+
+    #[test]
+    fn faulty_batch_validation_falls_back_to_individual_checks() {
+        // The batched validity check returns no results for this batch, so each note is
+        // checked individually: the first is valid, and the second has the wrong
+        // commitment.
+        let outputs = [
+            (
+                MockDomain,
+                full_output(BATCH_FAULTY_EK, 0b01, 7, MockCmx::for_note(0, 7)),
+            ),
+            (MockDomain, full_output(1, 0b01, 8, MockCmx::for_note(1, 8))),
+        ];
+
+        assert_eq!(
+            try_note_decryption(&[0], &outputs),
+            vec![Some(((MockNote { ivk: 0, value: 7 }, 0, ()), 0)), None]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "max_outputs must be non-zero")]
+    fn config_rejects_zero_max_outputs() {
+        BatchConfig::<MockDomain>::new().with_max_outputs(0);
+    }
+
+    #[test]
+    fn metadata_is_returned_with_each_decrypted_output() {
+        let outputs = [
+            (
+                MockDomain,
+                full_output(1, 0b10, 1, MockCmx::for_note(1, 1)),
+                "a",
+            ),
+            (
+                MockDomain,
+                full_output(2, 0b100, 2, MockCmx::for_note(2, 2)),
+                "b",
+            ),
+            (
+                MockDomain,
+                full_output(3, 0b01, 3, MockCmx::for_note(0, 3)),
+                "c",
+            ),
+            (
+                MockDomain,
+                full_output(INVALID_EPK, 0b01, 4, MockCmx::for_note(0, 4)),
+                "d",
+            ),
+            (
+                MockDomain,
+                full_output(5, 0b10, 5, MockCmx::for_note(1, 5)),
+                "e",
+            ),
+        ];
+        let compact_outputs = outputs
+            .iter()
+            .map(|(domain, output, metadata)| {
+                let enc_ciphertext = output.enc_ciphertext[..COMPACT_NOTE_SIZE]
+                    .try_into()
+                    .unwrap();
+                (
+                    *domain,
+                    MockOutput {
+                        ephemeral_key: output.ephemeral_key,
+                        cmx: output.cmx,
+                        enc_ciphertext,
+                    },
+                    *metadata,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Outputs that no IVK decrypts are omitted, and the others are returned in order
+        // with references to their own domain and metadata.
+        let results = try_note_decryption_with_metadata(&[0, 1], &outputs);
+        assert_eq!(
+            results
                 .iter()
-                .zip(ivks.iter().enumerate())
-                .find_map(|(key, (i, ivk))| {
-                    key.as_ref()
-                        .and_then(|key| decrypt_inner(domain, ivk, ephemeral_key, output, key))
-                        .map(|out| (out, i))
+                .map(|(domain, metadata, (note, _, _), ivk_index)| {
+                    (*domain as *const _, **metadata, note.value, ivk_index.0)
                 })
-        })
-        .collect::<Vec<Option<_>>>()
+                .collect::<Vec<_>>(),
+            vec![
+                (&outputs[0].0 as *const _, "a", 1, 1),
+                (&outputs[2].0 as *const _, "c", 3, 0),
+                (&outputs[4].0 as *const _, "e", 5, 1),
+            ]
+        );
+
+        let results = try_compact_note_decryption_with_metadata(&[0, 1], &compact_outputs);
+        assert_eq!(
+            results
+                .iter()
+                .map(|(domain, metadata, (note, _), ivk_index)| {
+                    (*domain as *const _, **metadata, note.value, ivk_index.0)
+                })
+                .collect::<Vec<_>>(),
+            vec![
+                (&compact_outputs[0].0 as *const _, "a", 1, 1),
+                (&compact_outputs[2].0 as *const _, "c", 3, 0),
+                (&compact_outputs[4].0 as *const _, "e", 5, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn lazy_decryption_matches_batch_decryption() {
+        // Hits under each IVK, a note that is only valid under the second IVK, a miss,
+        // and an invalid ephemeral key.
+        let cases = [
+            (1, 0b01, 1, MockCmx::for_note(0, 1)),
+            (2, 0b10, 2, MockCmx::for_note(1, 2)),
+            (3, 0b11, 3, MockCmx::for_note(1, 3)),
+            (4, 0b100, 4, MockCmx::for_note(2, 4)),
+            (INVALID_EPK, 0b01, 5, MockCmx::for_note(0, 5)),
+        ];
+        let outputs = cases
+            .iter()
+            .map(|&(ek, mask, value, cmx)| (MockDomain, full_output(ek, mask, value, cmx)))
+            .collect::<Vec<_>>();
+        let compact_outputs = cases
+            .iter()
+            .map(|&(ek, mask, value, cmx)| (MockDomain, compact_output(ek, mask, value, cmx)))
+            .collect::<Vec<_>>();
+
+        let expected = try_note_decryption(&[0, 1], &outputs);
+        assert_eq!(expected.iter().filter(|res| res.is_some()).count(), 3);
+        assert_eq!(
+            try_note_decryption_iter(&[0, 1], &outputs).collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(
+            try_compact_note_decryption_iter(&[0, 1], &compact_outputs).collect::<Vec<_>>(),
+            try_compact_note_decryption(&[0, 1], &compact_outputs)
+        );
+    }
+
+    #[test]
+    fn validate_inputs_reports_first_malformed_input() {
+        let valid = full_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let invalid = full_output(INVALID_EPK, 0b01, 1, MockCmx::for_note(0, 1));
+
+        assert_eq!(
+            validate_inputs(&[0], &[(MockDomain, valid.clone())]),
+            Ok(())
+        );
+        assert_eq!(
+            validate_inputs(&[], &[(MockDomain, valid.clone())]),
+            Err(BatchInputError::EmptyKeySet)
+        );
+        // An empty key set is reported in preference to malformed outputs.
+        assert_eq!(
+            validate_inputs(&[], &[(MockDomain, invalid.clone())]),
+            Err(BatchInputError::EmptyKeySet)
+        );
+        assert_eq!(
+            validate_inputs(
+                &[0],
+                &[
+                    (MockDomain, valid.clone()),
+                    (MockDomain, invalid.clone()),
+                    (MockDomain, valid),
+                    (MockDomain, invalid),
+                ]
+            ),
+            Err(BatchInputError::InvalidEphemeralKey(1))
+        );
+    }
+
+    #[test]
+    fn into_variants_append_batch_results() {
+        let cases = [
+            (1, 0b01, 1, MockCmx::for_note(0, 1)),
+            (2, 0b11, 2, MockCmx::for_note(1, 2)),
+            (3, 0b100, 3, MockCmx::for_note(2, 3)),
+            (INVALID_EPK, 0b01, 4, MockCmx::for_note(0, 4)),
+        ];
+        let outputs = cases
+            .iter()
+            .map(|&(ek, mask, value, cmx)| (MockDomain, full_output(ek, mask, value, cmx)))
+            .collect::<Vec<_>>();
+        let compact_outputs = cases
+            .iter()
+            .map(|&(ek, mask, value, cmx)| (MockDomain, compact_output(ek, mask, value, cmx)))
+            .collect::<Vec<_>>();
+
+        // Existing contents are kept, and the results are appended after them.
+        let existing = Some(((MockNote { ivk: 7, value: 7 }, 7, ()), 7));
+        let mut results = vec![existing, None];
+        try_note_decryption_into(&[0, 1], &outputs, &mut results);
+        assert_eq!(results[..2], [existing, None]);
+        assert_eq!(results[2..], try_note_decryption(&[0, 1], &outputs)[..]);
+
+        let existing = Some(((MockNote { ivk: 7, value: 7 }, 7), 7));
+        let mut results = vec![existing, None];
+        try_compact_note_decryption_into(&[0, 1], &compact_outputs, &mut results);
+        assert_eq!(results[..2], [existing, None]);
+        assert_eq!(
+            results[2..],
+            try_compact_note_decryption(&[0, 1], &compact_outputs)[..]
+        );
+    }
+
+    #[test]
+    fn config_skips_known_outputs_and_filtered_notes() {
+        let outputs = [
+            (MockDomain, full_output(1, 0b01, 1, MockCmx::for_note(0, 1))),
+            (MockDomain, full_output(2, 0b01, 2, MockCmx::for_note(0, 2))),
+            (MockDomain, full_output(3, 0b01, 3, MockCmx::for_note(0, 3))),
+        ];
+        let is_known = |cmx: &MockCmx| *cmx == MockCmx::for_note(0, 3);
+        let note_filter = |note: &MockNote| note.value != 2;
+        let config = BatchConfig::new()
+            .with_chunk_size(1)
+            .with_max_outputs(2)
+            .with_is_known(&is_known)
+            .with_note_filter(&note_filter);
+
+        let mut cursor = BatchCursor::new();
+        let mut cache = epk_cache(2);
+        let results =
+            try_note_decryption_with_config(&[0], &outputs, &config, &mut cursor, &mut cache);
+        assert_eq!(
+            results,
+            vec![Some(((MockNote { ivk: 0, value: 1 }, 0, ()), 0)), None]
+        );
+        assert_eq!(cursor.position(), 2);
+
+        let results =
+            try_note_decryption_with_config(&[0], &outputs, &config, &mut cursor, &mut cache);
+        assert_eq!(results, vec![None]);
+        assert!(cursor.is_complete(&outputs));
+    }
+
+    #[test]
+    fn epk_cache_evicts_oldest_entry_at_capacity() {
+        let ek = |b| EphemeralKeyBytes([b; 32]);
+        let mut cache = epk_cache(2);
+
+        assert_eq!(cache.get_or_prepare(&ek(1)), Some(&ek(1)));
+        assert_eq!(cache.get_or_prepare(&ek(2)), Some(&ek(2)));
+        assert_eq!(cache.get_or_prepare(&ek(3)), Some(&ek(3)));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.entries.contains_key(&ek(1).0));
+
+        // A hit does not change the eviction order.
+        assert_eq!(cache.get_or_prepare(&ek(2)), Some(&ek(2)));
+        assert_eq!(cache.get_or_prepare(&ek(1)), Some(&ek(1)));
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.entries.contains_key(&ek(2).0));
+        assert!(cache.entries.contains_key(&ek(3).0));
+    }
+
+    #[test]
+    fn config_decryption_consults_epk_cache() {
+        let outputs = [
+            (MockDomain, full_output(1, 0b01, 1, MockCmx::for_note(0, 1))),
+            (MockDomain, full_output(2, 0b01, 2, MockCmx::for_note(0, 2))),
+            (MockDomain, full_output(3, 0b01, 3, MockCmx::for_note(0, 3))),
+        ];
+        let config = BatchConfig::new().with_chunk_size(2);
+
+        // Cache the first output's ephemeral key as invalid, so that the output is only
+        // decrypted if its ephemeral key is parsed again.
+        let mut cache = epk_cache(2);
+        cache.entries.insert([1; 32], None);
+        cache.insertion_order.push_back([1; 32]);
+
+        let results = try_note_decryption_with_config(
+            &[0],
+            &outputs,
+            &config,
+            &mut BatchCursor::new(),
+            &mut cache,
+        );
+        assert_eq!(
+            results,
+            vec![
+                None,
+                Some(((MockNote { ivk: 0, value: 2 }, 0, ()), 0)),
+                Some(((MockNote { ivk: 0, value: 3 }, 0, ()), 0)),
+            ]
+        );
+
+        // The second chunk's ephemeral key evicted the oldest entry.
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.entries.contains_key(&[1; 32]));
+        assert!(cache.entries.contains_key(&[2; 32]));
+        assert!(cache.entries.contains_key(&[3; 32]));
+    }
+
+    #[test]
+    fn epk_cache_caches_invalid_epks() {
+        let invalid = EphemeralKeyBytes([INVALID_EPK; 32]);
+        let mut cache = epk_cache(2);
+
+        assert_eq!(cache.get_or_prepare(&invalid), None);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.entries.get(&invalid.0), Some(&None));
+
+        assert_eq!(cache.get_or_prepare(&invalid), None);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.insertion_order.len(), 1);
+    }
+
+    #[test]
+    fn key_set_groups_results_by_tag() {
+        let keys = KeySet::<_, MockDomain>::new()
+            .with_key("a", 0)
+            .with_key("b", 1)
+            .with_key("a", 2)
+            .with_key("c", 3);
+        let results = vec![Some(('x', 2)), None, Some(('y', 1)), Some(('z', 0))];
+
+        assert_eq!(
+            keys.group(results),
+            vec![
+                ("a", vec![(OutputIndex(0), 'x'), (OutputIndex(3), 'z')]),
+                ("b", vec![(OutputIndex(2), 'y')]),
+                ("c", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn by_account_maps_ivk_indices_to_tags() {
+        let keys = KeySet::new().with_key("a", 0).with_key("b", 1);
+        let outputs = [
+            (MockDomain, full_output(1, 0b10, 1, MockCmx::for_note(1, 1))),
+            (MockDomain, full_output(2, 0b01, 2, MockCmx::for_note(0, 2))),
+        ];
+
+        assert_eq!(
+            try_note_decryption_by_account(&keys, &outputs),
+            vec![
+                (
+                    "a",
+                    vec![(OutputIndex(1), (MockNote { ivk: 0, value: 2 }, 0, ()))]
+                ),
+                (
+                    "b",
+                    vec![(OutputIndex(0), (MockNote { ivk: 1, value: 1 }, 1, ()))]
+                ),
+            ]
+        );
+    }
+
+    struct VecSource {
+        ephemeral_key: Vec<u8>,
+        cmstar_bytes: Option<MockCmx>,
+        enc_ciphertext: Vec<u8>,
+    }
+
+    impl CompactOutputSource<MockDomain> for VecSource {
+        fn ephemeral_key(&self) -> &[u8] {
+            &self.ephemeral_key
+        }
+
+        fn cmstar_bytes(&self) -> Option<MockCmx> {
+            self.cmstar_bytes
+        }
+
+        fn enc_ciphertext(&self) -> &[u8] {
+            &self.enc_ciphertext
+        }
+    }
+
+    fn vec_source(ephemeral_key_len: usize, enc_ciphertext_len: usize) -> VecSource {
+        VecSource {
+            ephemeral_key: (0..ephemeral_key_len).map(|i| i as u8).collect(),
+            cmstar_bytes: Some(MockCmx::for_note(0, 1)),
+            enc_ciphertext: (0..enc_ciphertext_len).map(|i| i as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn compact_source_output_accepts_exact_lengths() {
+        let source = vec_source(32, COMPACT_NOTE_SIZE);
+
+        let output = CompactSourceOutput::new(&source).unwrap();
+        assert!(!output.was_truncated());
+        let lenient = CompactSourceOutput::new_lenient(&source).unwrap();
+        assert!(!lenient.was_truncated());
+        assert_eq!(lenient.ephemeral_key().0[..], source.ephemeral_key[..]);
+        assert_eq!(lenient.enc_ciphertext()[..], source.enc_ciphertext[..]);
+    }
+
+    #[test]
+    fn compact_source_output_lenient_truncates_oversized_fields() {
+        let source = vec_source(40, COMPACT_NOTE_SIZE + 8);
+        assert!(CompactSourceOutput::new(&source).is_none());
+
+        let output = CompactSourceOutput::new_lenient(&source).unwrap();
+        assert!(output.was_truncated());
+        assert_eq!(output.ephemeral_key().0[..], source.ephemeral_key[..32]);
+        assert_eq!(output.cmstar_bytes(), MockCmx::for_note(0, 1));
+        assert_eq!(
+            output.enc_ciphertext()[..],
+            source.enc_ciphertext[..COMPACT_NOTE_SIZE]
+        );
+    }
+
+    #[test]
+    fn compact_source_output_rejects_short_or_malformed_fields() {
+        for source in [
+            vec_source(31, COMPACT_NOTE_SIZE),
+            vec_source(32, COMPACT_NOTE_SIZE - 1),
+            VecSource {
+                cmstar_bytes: None,
+                ..vec_source(32, COMPACT_NOTE_SIZE)
+            },
+        ] {
+            assert!(CompactSourceOutput::new(&source).is_none());
+            assert!(CompactSourceOutput::new_lenient(&source).is_none());
+        }
+    }
+
+    #[test]
+    fn from_sources_decrypts_only_exact_length_sources() {
+        let source = |output: &MockOutput<COMPACT_NOTE_SIZE>, ek_len: usize, ct_len: usize| {
+            let mut ephemeral_key = output.ephemeral_key.0.to_vec();
+            ephemeral_key.resize(ek_len, 0);
+            let mut enc_ciphertext = output.enc_ciphertext.to_vec();
+            enc_ciphertext.resize(ct_len, 0);
+            VecSource {
+                ephemeral_key,
+                cmstar_bytes: Some(output.cmx),
+                enc_ciphertext,
+            }
+        };
+        let hit = compact_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let miss = compact_output(2, 0b10, 2, MockCmx::for_note(1, 2));
+
+        let sources = [
+            (MockDomain, source(&hit, 32, COMPACT_NOTE_SIZE)),
+            (MockDomain, source(&miss, 32, COMPACT_NOTE_SIZE)),
+            (MockDomain, source(&hit, 31, COMPACT_NOTE_SIZE)),
+            (MockDomain, source(&hit, 32, COMPACT_NOTE_SIZE - 1)),
+            (MockDomain, source(&hit, 40, COMPACT_NOTE_SIZE)),
+            (MockDomain, source(&hit, 32, COMPACT_NOTE_SIZE + 8)),
+        ];
+        let decrypted = Some(((MockNote { ivk: 0, value: 1 }, 0), 0));
+        assert_eq!(
+            try_compact_note_decryption_from_sources(&[0], &sources),
+            vec![decrypted, None, None, None, None, None]
+        );
+
+        // Over-long sources can be decrypted through lenient views.
+        let views = sources
+            .iter()
+            .filter_map(|(domain, source)| {
+                CompactSourceOutput::new_lenient(source).map(|view| (*domain, view))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            views
+                .iter()
+                .map(|(_, view)| view.was_truncated())
+                .collect::<Vec<_>>(),
+            vec![false, false, true, true]
+        );
+        assert_eq!(
+            try_compact_note_decryption(&[0], &views),
+            vec![decrypted, None, decrypted, decrypted]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    fn compact_records(outputs: &[MockOutput<COMPACT_NOTE_SIZE>]) -> Vec<u8> {
+        let mut records = Vec::new();
+        for output in outputs {
+            let mut cmstar_bytes = [0; 32];
+            cmstar_bytes[..2].copy_from_slice(&output.cmx.0);
+            records.extend_from_slice(&output.ephemeral_key.0);
+            records.extend_from_slice(&cmstar_bytes);
+            records.extend_from_slice(&output.enc_ciphertext);
+        }
+        records
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_reader_rejects_trailing_partial_record() {
+        let mut records = compact_records(&[compact_output(1, 0b01, 1, MockCmx::for_note(0, 1))]);
+        records.extend_from_slice(&[0; 10]);
+
+        let mut decrypted = vec![];
+        let err = try_compact_note_decryption_from_reader(
+            &MockDomain,
+            &[0],
+            &records[..],
+            &BatchConfig::new().with_chunk_size(1),
+            |i, _, _, _| decrypted.push(i),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        // Only the records in chunks that were read in full are decrypted.
+        assert_eq!(decrypted, vec![0]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_reader_indexes_records_across_partial_chunks() {
+        let records = compact_records(&[
+            compact_output(1, 0b10, 1, MockCmx::for_note(1, 1)),
+            compact_output(2, 0b01, 2, MockCmx::for_note(0, 2)),
+            compact_output(3, 0b10, 3, MockCmx::for_note(1, 3)),
+            compact_output(4, 0b10, 4, MockCmx::for_note(1, 4)),
+            compact_output(5, 0b01, 5, MockCmx::for_note(0, 5)),
+        ]);
+
+        let mut decrypted = vec![];
+        let read = try_compact_note_decryption_from_reader(
+            &MockDomain,
+            &[0],
+            &records[..],
+            &BatchConfig::new().with_chunk_size(2),
+            |i, note, _, ivk_idx| decrypted.push((i, note.value, ivk_idx.0)),
+        )
+        .unwrap();
+        assert_eq!(read, 5);
+        assert_eq!(decrypted, vec![(1, 2, 0), (4, 5, 0)]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_reader_skips_records_with_invalid_cmstar_bytes() {
+        let mut records = compact_records(&[
+            compact_output(1, 0b01, 1, MockCmx::for_note(0, 1)),
+            compact_output(2, 0b01, 2, MockCmx::for_note(0, 2)),
+            compact_output(3, 0b01, 3, MockCmx::for_note(0, 3)),
+        ]);
+        // Make the second record's `cmstar_bytes` a non-canonical encoding.
+        records[COMPACT_RECORD_SIZE + 32 + 31] = 1;
+
+        let mut decrypted = vec![];
+        let read = try_compact_note_decryption_from_reader(
+            &MockDomain,
+            &[0],
+            &records[..],
+            &BatchConfig::new().with_chunk_size(2),
+            |i, note, _, _| decrypted.push((i, note.value)),
+        )
+        .unwrap();
+        assert_eq!(read, 3);
+        assert_eq!(decrypted, vec![(0, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn trial_and_finalize_phases_match_compact_decryption() {
+        let outputs = [
+            (
+                MockDomain,
+                compact_output(1, 0b01, 1, MockCmx::for_note(0, 1)),
+            ),
+            (
+                MockDomain,
+                compact_output(2, 0b11, 2, MockCmx::for_note(1, 2)),
+            ),
+            (
+                MockDomain,
+                compact_output(3, 0b01, 3, MockCmx::for_note(1, 3)),
+            ),
+            (
+                MockDomain,
+                compact_output(INVALID_EPK, 0b01, 4, MockCmx::for_note(0, 4)),
+            ),
+        ];
+        let ivks = [0, 1];
+
+        let candidates = trial_phase(&ivks, &outputs);
+        // The output with an invalid ephemeral key produces no candidates.
+        assert_eq!(candidates.len(), 6);
+        assert_eq!(
+            finalize_phase(&ivks, &outputs, candidates),
+            try_compact_note_decryption(&ivks, &outputs),
+        );
+    }
+
+    fn candidate(
+        output_index: usize,
+        ivk_index: usize,
+        ivk_mask: u8,
+        value: u8,
+    ) -> TrialCandidate<MockDomain> {
+        let mut plaintext = [0; COMPACT_NOTE_SIZE];
+        plaintext[0] = ivk_mask;
+        plaintext[1] = value;
+        TrialCandidate {
+            output_index: OutputIndex(output_index),
+            ivk_index: IvkIndex(ivk_index),
+            key: Some([0; 32]),
+            plaintext,
+        }
+    }
+
+    #[test]
+    fn finalize_phase_ignores_out_of_range_and_duplicate_candidates() {
+        let outputs: [(MockDomain, MockOutput<COMPACT_NOTE_SIZE>); 2] = [
+            (
+                MockDomain,
+                compact_output(1, 0b01, 1, MockCmx::for_note(0, 1)),
+            ),
+            (
+                MockDomain,
+                compact_output(2, 0b01, 2, MockCmx::for_note(0, 2)),
+            ),
+        ];
+        // Two copies of the same IVK, so that either can decrypt each output.
+        let ivks = [0, 0];
+
+        let results = finalize_phase(
+            &ivks,
+            &outputs,
+            vec![
+                candidate(2, 0, 0b01, 1),
+                candidate(0, 2, 0b01, 1),
+                candidate(0, 1, 0b01, 1),
+                candidate(0, 0, 0b01, 1),
+                candidate(1, 0, 0b01, 9),
+                candidate(1, 1, 0b01, 2),
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Some(((MockNote { ivk: 0, value: 1 }, 0), 1)),
+                Some(((MockNote { ivk: 0, value: 2 }, 0), 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn index_arithmetic_and_display() {
+        let mut output_index = OutputIndex(3) + 2;
+        assert_eq!(output_index, OutputIndex(5));
+        output_index -= 1;
+        assert_eq!(output_index - OutputIndex(1), 3);
+        assert_eq!(output_index - 4, OutputIndex(0));
+        assert_eq!(format!("{}", output_index), "4");
+
+        let mut ivk_index = IvkIndex::from(1);
+        ivk_index += 1;
+        assert_eq!(usize::from(ivk_index - 2), 0);
+        assert_eq!(format!("{}", ivk_index), "2");
+    }
+
+    #[test]
+    fn trial_candidate_into_parts() {
+        let (output_index, ivk_index, key, plaintext) = candidate(3, 1, 0b01, 5).into_parts();
+        assert_eq!(output_index, OutputIndex(3));
+        assert_eq!(ivk_index, IvkIndex(1));
+        assert_eq!(key, [0; 32]);
+        assert_eq!(plaintext[..2], [0b01, 5]);
+    }
+
+    #[test]
+    fn scan_outputs_reports_received_and_sent_notes() {
+        let mut unrecoverable = sent_output(3, 0b10, 3, MockCmx::for_note(1, 3));
+        unrecoverable.out_ciphertext[0] ^= 1;
+        let outputs = [
+            (MockDomain, sent_output(1, 0b01, 1, MockCmx::for_note(0, 1))),
+            (MockDomain, sent_output(2, 0b10, 2, MockCmx::for_note(1, 2))),
+            (MockDomain, unrecoverable),
+        ];
+
+        let hits = scan_outputs(&[0], &[()], &outputs);
+        assert_eq!(hits.len(), 3);
+        assert!(matches!(
+            hits[0],
+            Some(ScanHit::Received {
+                note: MockNote { ivk: 0, value: 1 },
+                recipient: 0,
+                ivk_index: IvkIndex(0),
+                ..
+            })
+        ));
+        assert!(matches!(
+            hits[1],
+            Some(ScanHit::Sent {
+                note: MockNote { ivk: 1, value: 2 },
+                recipient: 1,
+                ovk_index: 0,
+                ..
+            })
+        ));
+        assert!(hits[2].is_none());
+
+        // Without OVKs, only received notes are found.
+        let hits = scan_outputs(&[0], &[], &outputs);
+        assert!(matches!(hits[0], Some(ScanHit::Received { .. })));
+        assert!(hits[1].is_none());
+    }
+
+    #[test]
+    fn mixed_outputs_decrypt_by_kind() {
+        let full = full_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let compact = compact_output(2, 0b10, 2, MockCmx::for_note(1, 2));
+        let invalid = compact_output(3, 0b01, 3, MockCmx::for_note(1, 3));
+        let outputs = [
+            (MockDomain, MixedOutput::Full(&full)),
+            (MockDomain, MixedOutput::Compact(&compact)),
+            (MockDomain, MixedOutput::Compact(&invalid)),
+        ];
+
+        let results = try_mixed_note_decryption(&[0, 1], &outputs);
+        assert_eq!(results.len(), 3);
+        assert!(matches!(
+            results[0],
+            Some((
+                MixedDecryption::Full(MockNote { ivk: 0, value: 1 }, 0, ()),
+                0
+            ))
+        ));
+        assert!(matches!(
+            results[1],
+            Some((
+                MixedDecryption::Compact(MockNote { ivk: 1, value: 2 }, 1),
+                1
+            ))
+        ));
+        assert!(results[2].is_none());
+    }
+
+    #[test]
+    fn tiered_decryption_decrypts_memos_from_full_from() {
+        let outputs = [
+            (MockDomain, full_output(1, 0b01, 1, MockCmx::for_note(0, 1))),
+            (MockDomain, full_output(2, 0b01, 2, MockCmx::for_note(0, 2))),
+            (MockDomain, full_output(3, 0b10, 3, MockCmx::for_note(1, 3))),
+        ];
+
+        let results = try_tiered_note_decryption(&[0, 1], &outputs, 1);
+        assert!(matches!(
+            results[0],
+            Some((
+                MixedDecryption::Compact(MockNote { ivk: 0, value: 1 }, 0),
+                0
+            ))
+        ));
+        assert!(matches!(
+            results[1],
+            Some((
+                MixedDecryption::Full(MockNote { ivk: 0, value: 2 }, 0, ()),
+                0
+            ))
+        ));
+        assert!(matches!(
+            results[2],
+            Some((
+                MixedDecryption::Full(MockNote { ivk: 1, value: 3 }, 1, ()),
+                1
+            ))
+        ));
+
+        // `full_from` past the end decrypts every output as compact.
+        let results = try_tiered_note_decryption(&[0, 1], &outputs, 10);
+        assert!(results
+            .iter()
+            .all(|res| matches!(res, Some((MixedDecryption::Compact(..), _)))));
+    }
+
+    #[test]
+    fn decryption_results_index_by_output() {
+        let keys = KeySet::<_, MockDomain>::new()
+            .with_key("a", 0)
+            .with_key("b", 1);
+        let outputs = [
+            (MockDomain, full_output(1, 0b10, 1, MockCmx::for_note(1, 1))),
+            (MockDomain, full_output(2, 0b00, 2, MockCmx::for_note(0, 2))),
+            (MockDomain, full_output(3, 0b01, 3, MockCmx::for_note(0, 3))),
+        ];
+
+        let results = DecryptionResults::from(try_note_decryption(keys.ivks(), &outputs));
+        assert_eq!(results.len(), 3);
+        assert!(!results.is_empty());
+
+        let note = MockNote { ivk: 1, value: 1 };
+        assert_eq!(
+            results.get(OutputIndex(0)),
+            Some((&(note, 1, ()), IvkIndex(1)))
+        );
+        assert_eq!(results.get(OutputIndex(1)), None);
+        assert_eq!(results.get(OutputIndex(3)), None);
+
+        assert_eq!(
+            results.get_for_ivk(OutputIndex(0), IvkIndex(1)),
+            Some(&(note, 1, ()))
+        );
+        assert_eq!(results.get_for_ivk(OutputIndex(0), IvkIndex(0)), None);
+        assert_eq!(
+            results.get_for_tag(OutputIndex(0), &keys, &"b"),
+            Some(&(note, 1, ()))
+        );
+        assert_eq!(results.get_for_tag(OutputIndex(0), &keys, &"a"), None);
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|(i, (note, _, _), ivk_index)| (i, note.value, ivk_index))
+                .collect::<Vec<_>>(),
+            vec![
+                (OutputIndex(0), 1, IvkIndex(1)),
+                (OutputIndex(2), 3, IvkIndex(0))
+            ]
+        );
+        assert_eq!(results.into_inner().len(), 3);
+    }
 }
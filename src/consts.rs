@@ -0,0 +1,72 @@
+//! The sizes and offsets used by in-band secret distribution.
+//!
+//! These are fixed by the [Zcash Protocol Specification] for Sapling and Orchard, and
+//! are provided in one place for downstream code (such as FFI bindings and conformance
+//! tests) that needs to agree with this crate. A change to any of these values is a
+//! breaking change.
+//!
+//! [Zcash Protocol Specification]: https://zips.z.cash/protocol/protocol.pdf#saplingandorchardinband
+
+use core::ops::Range;
+
+/// The size of a compact note.
+pub const COMPACT_NOTE_SIZE: usize = 1 + // version
+    11 + // diversifier
+    8  + // value
+    32; // rseed (or rcm prior to ZIP 212)
+/// The size of [`NotePlaintextBytes`](crate::NotePlaintextBytes).
+pub const NOTE_PLAINTEXT_SIZE: usize = COMPACT_NOTE_SIZE + 512;
+/// The size of [`OutPlaintextBytes`](crate::OutPlaintextBytes).
+pub const OUT_PLAINTEXT_SIZE: usize = 32 + // pk_d
+    32; // esk
+/// The size of a ChaCha20Poly1305 authentication tag.
+pub const AEAD_TAG_SIZE: usize = 16;
+/// The size of the ChaCha20Poly1305 nonce.
+///
+/// Every key is used to encrypt exactly one plaintext, so the nonce is always zero.
+pub const NONCE_SIZE: usize = 12;
+/// The size of a [`Domain::SymmetricKey`](crate::Domain::SymmetricKey), as required by
+/// ChaCha20Poly1305.
+pub const SYMMETRIC_KEY_SIZE: usize = 32;
+/// The size of an encrypted note plaintext.
+pub const ENC_CIPHERTEXT_SIZE: usize = NOTE_PLAINTEXT_SIZE + AEAD_TAG_SIZE;
+/// The size of an encrypted outgoing plaintext.
+pub const OUT_CIPHERTEXT_SIZE: usize = OUT_PLAINTEXT_SIZE + AEAD_TAG_SIZE;
+
+/// The offset in the ChaCha20 keystream at which encryption of the note plaintext
+/// begins.
+///
+/// ChaCha20Poly1305 uses the first 64-byte block of the keystream to derive the Poly1305
+/// key, so the ciphertext is encrypted from block 1 onwards.
+pub const CHACHA_POLY_KEY_BLOCK: u64 = 64;
+/// The offset in the ChaCha20 keystream at which encryption of the compact note (the
+/// first [`COMPACT_NOTE_SIZE`] bytes of the note plaintext) begins.
+pub const COMPACT_KEYSTREAM_OFFSET: u64 = CHACHA_POLY_KEY_BLOCK;
+/// The offset in the ChaCha20 keystream at which encryption of the memo begins.
+///
+/// After seeking a ChaCha20 keystream to this offset, applying it to
+/// `encCiphertext[COMPACT_NOTE_SIZE..NOTE_PLAINTEXT_SIZE]` decrypts the memo (without
+/// authenticating it). The remaining [`AEAD_TAG_SIZE`] bytes are the authentication tag,
+/// and must not be decrypted.
+///
+/// This is intended for partial or streaming decryption, where the rest of the note
+/// plaintext is not needed.
+pub const MEMO_KEYSTREAM_OFFSET: u64 = CHACHA_POLY_KEY_BLOCK + COMPACT_NOTE_SIZE as u64;
+
+/// Returns the range of the ChaCha20 keystream that encrypts the compact note, which is
+/// `encCiphertext[..COMPACT_NOTE_SIZE]`.
+///
+/// A keystream seeked to the start of this range decrypts the compact note without
+/// authenticating it, as for [`crate::try_compact_note_decryption`].
+pub const fn compact_keystream_range() -> Range<u64> {
+    COMPACT_KEYSTREAM_OFFSET..MEMO_KEYSTREAM_OFFSET
+}
+
+/// Returns the range of the ChaCha20 keystream that encrypts the memo, which is
+/// `encCiphertext[COMPACT_NOTE_SIZE..NOTE_PLAINTEXT_SIZE]`.
+///
+/// A keystream seeked to the start of this range decrypts the memo without
+/// authenticating it. See [`MEMO_KEYSTREAM_OFFSET`].
+pub const fn memo_keystream_range() -> Range<u64> {
+    MEMO_KEYSTREAM_OFFSET..CHACHA_POLY_KEY_BLOCK + NOTE_PLAINTEXT_SIZE as u64
+}
@@ -0,0 +1,133 @@
+//! Entry points for fuzzing the decryption APIs.
+//!
+//! Each function in this module interprets arbitrary bytes as the public components of a
+//! shielded output, and passes them to the corresponding decryption API in this crate.
+//! The functions never panic for any input unless the [`Domain`] implementation does,
+//! so they can be called directly from `cargo fuzz` targets, both in this repository and
+//! in crates that implement [`Domain`].
+//!
+//! Outputs are encoded as `ephemeral_key || cmstar_bytes || ciphertext`, where
+//! `ephemeral_key` and `cmstar_bytes` are 32 bytes each. Inputs whose `cmstar_bytes`
+//! cannot be converted to `D::ExtractedCommitmentBytes` are ignored, as are trailing
+//! bytes.
+
+use crate::consts::{COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE, OUT_CIPHERTEXT_SIZE};
+use crate::{
+    try_compact_note_decryption, try_note_decryption, try_output_recovery_with_ock, Domain,
+    EphemeralKeyBytes, OutgoingCipherKey, ShieldedOutput,
+};
+
+struct FuzzOutput<D: Domain, const CS: usize> {
+    ephemeral_key: EphemeralKeyBytes,
+    cmstar_bytes: D::ExtractedCommitmentBytes,
+    enc_ciphertext: [u8; CS],
+}
+
+impl<D: Domain, const CS: usize> ShieldedOutput<D, CS> for FuzzOutput<D, CS>
+where
+    D::ExtractedCommitmentBytes: Clone,
+{
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        self.ephemeral_key
+    }
+
+    fn cmstar_bytes(&self) -> D::ExtractedCommitmentBytes {
+        self.cmstar_bytes.clone()
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; CS] {
+        &self.enc_ciphertext
+    }
+}
+
+/// Splits `N` bytes off the front of `data`.
+fn take<const N: usize>(data: &mut &[u8]) -> Option<[u8; N]> {
+    if data.len() < N {
+        return None;
+    }
+    let (head, tail) = data.split_at(N);
+    *data = tail;
+    head.try_into().ok()
+}
+
+fn parse_output<D: Domain, const CS: usize>(data: &mut &[u8]) -> Option<FuzzOutput<D, CS>>
+where
+    D::ExtractedCommitmentBytes: for<'a> TryFrom<&'a [u8]>,
+{
+    Some(FuzzOutput {
+        ephemeral_key: EphemeralKeyBytes(take(data)?),
+        cmstar_bytes: D::ExtractedCommitmentBytes::try_from(&take::<32>(data)?[..]).ok()?,
+        enc_ciphertext: take(data)?,
+    })
+}
+
+/// Fuzzes [`try_note_decryption`] with the output encoded in `data`, split into its
+/// `ephemeral_key`, `cmstar_bytes`, and ciphertext fields.
+pub fn fuzz_split_ciphertext<D: Domain>(domain: &D, ivk: &D::IncomingViewingKey, data: &[u8])
+where
+    D::ExtractedCommitmentBytes: Clone + for<'a> TryFrom<&'a [u8]>,
+{
+    let mut data = data;
+    if let Some(output) = parse_output::<D, ENC_CIPHERTEXT_SIZE>(&mut data) {
+        let _ = try_note_decryption(domain, ivk, &output);
+    }
+}
+
+/// Fuzzes [`try_compact_note_decryption`] with the compact output encoded in `data`.
+///
+/// Compact ciphertexts are not authenticated, so with a real [`Domain`] almost every
+/// input fails to parse. This is intended to be called with a mock [`Domain`] whose
+/// plaintext parsing accepts most inputs, so that the validity checks are reached.
+pub fn fuzz_compact_decrypt_mock_domain<D: Domain>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    data: &[u8],
+) where
+    D::ExtractedCommitmentBytes: Clone + for<'a> TryFrom<&'a [u8]>,
+{
+    let mut data = data;
+    if let Some(output) = parse_output::<D, COMPACT_NOTE_SIZE>(&mut data) {
+        let _ = try_compact_note_decryption(domain, ivk, &output);
+    }
+}
+
+/// Fuzzes [`try_output_recovery_with_ock`].
+///
+/// `data` is encoded as `ock || output || out_ciphertext`, where `ock` is 32 bytes and
+/// `output` is encoded as described in the [module documentation](self).
+pub fn fuzz_out_ciphertext_recovery<D: Domain>(domain: &D, data: &[u8])
+where
+    D::ExtractedCommitmentBytes: Clone + for<'a> TryFrom<&'a [u8]>,
+{
+    let mut data = data;
+    let inputs = (|| {
+        let ock = OutgoingCipherKey(take(&mut data)?);
+        let output = parse_output::<D, ENC_CIPHERTEXT_SIZE>(&mut data)?;
+        let out_ciphertext = take::<OUT_CIPHERTEXT_SIZE>(&mut data)?;
+        Some((ock, output, out_ciphertext))
+    })();
+
+    if let Some((ock, output, out_ciphertext)) = inputs {
+        let _ = try_output_recovery_with_ock(domain, &ock, &output, &out_ciphertext);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{
+        fuzz_compact_decrypt_mock_domain, fuzz_out_ciphertext_recovery, fuzz_split_ciphertext,
+    };
+    use crate::test_domain::MockDomain;
+
+    #[test]
+    fn entry_points_accept_arbitrary_bytes() {
+        for len in [0, 31, 64, 116, 700, 900] {
+            for byte in [0x00, 0x01, 0xff] {
+                let data = &[byte; 900][..len];
+                fuzz_split_ciphertext(&MockDomain, &0, data);
+                fuzz_compact_decrypt_mock_domain(&MockDomain, &0, data);
+                fuzz_out_ciphertext_recovery(&MockDomain, data);
+            }
+        }
+    }
+}
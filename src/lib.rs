@@ -19,10 +19,23 @@
 #![deny(unsafe_code)]
 // TODO: #![deny(missing_docs)]
 
+#[cfg(all(
+    feature = "force-soft",
+    not(all(chacha20_force_soft, poly1305_force_soft))
+))]
+compile_error!(
+    "The `force-soft` feature requires building with \
+     RUSTFLAGS=\"--cfg chacha20_force_soft --cfg poly1305_force_soft\""
+);
+
 use core::fmt::{self, Write};
 
 #[cfg(feature = "alloc")]
 extern crate alloc;
+#[cfg(feature = "secrecy")]
+extern crate dep_secrecy as secrecy;
+#[cfg(feature = "std")]
+extern crate std;
 #[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
@@ -34,31 +47,92 @@ use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, KeyInit};
 use cipher::KeyIvInit;
 
 use rand_core::RngCore;
-use subtle::{Choice, ConstantTimeEq};
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
-#[cfg(feature = "alloc")]
-#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
 pub mod batch;
+pub mod consts;
+
+#[cfg(feature = "fuzz")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fuzz")))]
+pub mod fuzz;
+
+#[cfg(feature = "secrecy")]
+#[cfg_attr(docsrs, doc(cfg(feature = "secrecy")))]
+pub mod secret;
+
+pub mod stages;
+
+#[cfg(feature = "test-dependencies")]
+#[cfg_attr(docsrs, doc(cfg(feature = "test-dependencies")))]
+pub mod testing;
+
+#[cfg(all(test, feature = "alloc"))]
+mod test_domain;
+
+#[cfg(feature = "insecure-debug")]
+#[cfg_attr(docsrs, doc(cfg(feature = "insecure-debug")))]
+pub mod transcript;
+
+/// Re-exports of the items needed to implement [`Domain`] and [`ShieldedOutput`].
+///
+/// ```
+/// use zcash_note_encryption::prelude::*;
+/// ```
+pub mod prelude {
+    #[cfg(feature = "alloc")]
+    pub use crate::BatchDomain;
+    pub use crate::{
+        Domain, EphemeralKeyBytes, NotePlaintextBytes, OutPlaintextBytes, OutgoingCipherKey,
+        OutputSummary, RecoverableOutput, ShieldedOutput, COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE,
+        NOTE_PLAINTEXT_SIZE, OUT_CIPHERTEXT_SIZE, OUT_PLAINTEXT_SIZE, SYMMETRIC_KEY_SIZE,
+    };
+}
+
+use consts::NONCE_SIZE;
+pub use consts::{
+    compact_keystream_range, memo_keystream_range, CHACHA_POLY_KEY_BLOCK, COMPACT_KEYSTREAM_OFFSET,
+    COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE, MEMO_KEYSTREAM_OFFSET, NOTE_PLAINTEXT_SIZE,
+    OUT_CIPHERTEXT_SIZE, OUT_PLAINTEXT_SIZE, SYMMETRIC_KEY_SIZE,
+};
+
+/// Formats a byte slice as lowercase hex, without allocating.
+///
+/// The [`Debug`](fmt::Debug) implementation quotes the hex string, for use in the
+/// `Debug` implementations of byte newtypes; the [`Display`](fmt::Display)
+/// implementation does not.
+///
+/// This must not be used to format secret values.
+pub struct HexFmt<'b>(pub &'b [u8]);
+
+impl<'b> fmt::Display for HexFmt<'b> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            f.write_fmt(format_args!("{:02x}", b))?;
+        }
+        Ok(())
+    }
+}
 
-/// The size of a compact note.
-pub const COMPACT_NOTE_SIZE: usize = 1 + // version
-    11 + // diversifier
-    8  + // value
-    32; // rseed (or rcm prior to ZIP 212)
-/// The size of [`NotePlaintextBytes`].
-pub const NOTE_PLAINTEXT_SIZE: usize = COMPACT_NOTE_SIZE + 512;
-/// The size of [`OutPlaintextBytes`].
-pub const OUT_PLAINTEXT_SIZE: usize = 32 + // pk_d
-    32; // esk
-const AEAD_TAG_SIZE: usize = 16;
-/// The size of an encrypted note plaintext.
-pub const ENC_CIPHERTEXT_SIZE: usize = NOTE_PLAINTEXT_SIZE + AEAD_TAG_SIZE;
-/// The size of an encrypted outgoing plaintext.
-pub const OUT_CIPHERTEXT_SIZE: usize = OUT_PLAINTEXT_SIZE + AEAD_TAG_SIZE;
+impl<'b> fmt::Debug for HexFmt<'b> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        fmt::Display::fmt(self, f)?;
+        f.write_char('"')
+    }
+}
 
 /// A symmetric key that can be used to recover a single Sapling or Orchard output.
 pub struct OutgoingCipherKey(pub [u8; 32]);
 
+impl fmt::Debug for OutgoingCipherKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OutgoingCipherKey(..)")
+    }
+}
+
 impl From<[u8; 32]> for OutgoingCipherKey {
     fn from(ock: [u8; 32]) -> Self {
         OutgoingCipherKey(ock)
@@ -74,22 +148,11 @@ impl AsRef<[u8]> for OutgoingCipherKey {
 /// Newtype representing the byte encoding of an [`EphemeralPublicKey`].
 ///
 /// [`EphemeralPublicKey`]: Domain::EphemeralPublicKey
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct EphemeralKeyBytes(pub [u8; 32]);
 
 impl fmt::Debug for EphemeralKeyBytes {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        struct HexFmt<'b>(&'b [u8]);
-        impl<'b> fmt::Debug for HexFmt<'b> {
-            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-                f.write_char('"')?;
-                for b in self.0 {
-                    f.write_fmt(format_args!("{:02x}", b))?;
-                }
-                f.write_char('"')
-            }
-        }
-
         f.debug_tuple("EphemeralKeyBytes")
             .field(&HexFmt(&self.0))
             .finish()
@@ -114,15 +177,192 @@ impl ConstantTimeEq for EphemeralKeyBytes {
     }
 }
 
+impl ConditionallySelectable for EphemeralKeyBytes {
+    fn conditional_select(a: &Self, b: &Self, choice: Choice) -> Self {
+        let mut bytes = a.0;
+        conditional_assign_bytes(&mut bytes, &b.0, choice);
+        EphemeralKeyBytes(bytes)
+    }
+}
+
+/// Sets `dst` to `src` if `choice == 1`, in constant time.
+fn conditional_assign_bytes(dst: &mut [u8], src: &[u8], choice: Choice) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        d.conditional_assign(s, choice);
+    }
+}
+
 /// Newtype representing the byte encoding of a note plaintext.
 pub struct NotePlaintextBytes(pub [u8; NOTE_PLAINTEXT_SIZE]);
 /// Newtype representing the byte encoding of a outgoing plaintext.
 pub struct OutPlaintextBytes(pub [u8; OUT_PLAINTEXT_SIZE]);
 
+impl NotePlaintextBytes {
+    /// Sets `self` to `other` if `choice == 1`, in constant time.
+    ///
+    /// This type does not implement [`ConditionallySelectable`] because it is not `Copy`.
+    pub fn conditional_assign(&mut self, other: &Self, choice: Choice) {
+        conditional_assign_bytes(&mut self.0, &other.0, choice);
+    }
+}
+
+impl OutPlaintextBytes {
+    /// Sets `self` to `other` if `choice == 1`, in constant time.
+    ///
+    /// This type does not implement [`ConditionallySelectable`] because it is not `Copy`.
+    pub fn conditional_assign(&mut self, other: &Self, choice: Choice) {
+        conditional_assign_bytes(&mut self.0, &other.0, choice);
+    }
+}
+
+impl fmt::Debug for OutPlaintextBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OutPlaintextBytes(..)")
+    }
+}
+
+/// Implements `Zeroize` and `ZeroizeOnDrop` for a newtype around a byte array.
+#[cfg(feature = "zeroize")]
+macro_rules! impl_zeroize_on_drop {
+    ($name:ident) => {
+        #[cfg_attr(docsrs, doc(cfg(feature = "zeroize")))]
+        impl Zeroize for $name {
+            fn zeroize(&mut self) {
+                self.0.zeroize();
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                self.zeroize();
+            }
+        }
+
+        #[cfg_attr(docsrs, doc(cfg(feature = "zeroize")))]
+        impl ZeroizeOnDrop for $name {}
+    };
+}
+
+#[cfg(feature = "zeroize")]
+impl_zeroize_on_drop!(OutgoingCipherKey);
+#[cfg(feature = "zeroize")]
+impl_zeroize_on_drop!(NotePlaintextBytes);
+#[cfg(feature = "zeroize")]
+impl_zeroize_on_drop!(OutPlaintextBytes);
+
+/// Wipes a temporary buffer holding secret data, if the `zeroize` feature is enabled.
+#[inline]
+fn wipe(_buf: &mut [u8]) {
+    #[cfg(feature = "zeroize")]
+    _buf.zeroize();
+}
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 enum NoteValidity {
     Valid,
-    Invalid,
+    Invalid(NoteValidityError),
+}
+
+/// The reason that a successfully decrypted note was rejected as invalid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteValidityError {
+    /// The note commitment computed from the note does not match the `cmstar_bytes` field
+    /// of the output.
+    CommitmentMismatch,
+    /// The ephemeral public key derived from the note does not match the `ephemeral_key`
+    /// field of the output.
+    EphemeralKeyMismatch,
+}
+
+impl fmt::Display for NoteValidityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteValidityError::CommitmentMismatch => {
+                write!(f, "note commitment does not match the output")
+            }
+            NoteValidityError::EphemeralKeyMismatch => {
+                write!(f, "ephemeral key does not match the output")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl std::error::Error for NoteValidityError {}
+
+/// The reason that trial decryption or output recovery failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecryptionError {
+    /// The `ephemeral_key` field of the output is not a valid encoding of an
+    /// `EphemeralPublicKey`.
+    InvalidEphemeralKey,
+    /// The ciphertext could not be decrypted with the derived key.
+    ///
+    /// For trial decryption with an `ivk`, this is the expected result for outputs that
+    /// were not sent to that `ivk`.
+    DecryptionFailed,
+    /// The note plaintext was decrypted, but could not be parsed.
+    ///
+    /// Compact note ciphertexts are not authenticated, so for compact trial decryption
+    /// this is the expected result for outputs that were not sent to the `ivk`.
+    InvalidPlaintext,
+    /// The outgoing plaintext was decrypted, but its `pk_d` or `esk` could not be
+    /// parsed.
+    InvalidOutPlaintext,
+    /// The `esk` used for output recovery does not match the `esk` derived from the
+    /// decrypted note.
+    InconsistentEsk,
+    /// The decrypted note is not valid for the output.
+    InvalidNote(NoteValidityError),
+}
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecryptionError::InvalidEphemeralKey => write!(f, "invalid ephemeral key"),
+            DecryptionError::DecryptionFailed => write!(f, "decryption failed"),
+            DecryptionError::InvalidPlaintext => write!(f, "invalid note plaintext"),
+            DecryptionError::InvalidOutPlaintext => write!(f, "invalid outgoing plaintext"),
+            DecryptionError::InconsistentEsk => {
+                write!(f, "esk is inconsistent with the decrypted note")
+            }
+            DecryptionError::InvalidNote(e) => write!(f, "invalid note: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl std::error::Error for DecryptionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecryptionError::InvalidNote(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl NoteValidity {
+    fn into_result(self) -> Result<(), NoteValidityError> {
+        match self {
+            NoteValidity::Valid => Ok(()),
+            NoteValidity::Invalid(e) => Err(e),
+        }
+    }
+}
+
+/// Checks that a previously decrypted note is consistent with the given output.
+///
+/// This is useful for notes decrypted from unconfirmed data (such as mempool
+/// transactions), which can be re-verified against the corresponding mined output
+/// without repeating trial decryption. It performs the same checks on the note that
+/// trial decryption does, against the output's `ephemeral_key` and `cmstar_bytes`.
+pub fn check_note_against_output<D: Domain, Output: ShieldedOutput<D, CS>, const CS: usize>(
+    note: &D::Note,
+    output: &Output,
+) -> Result<(), NoteValidityError> {
+    check_note_validity::<D>(note, &output.ephemeral_key(), &output.cmstar_bytes()).into_result()
 }
 
 /// Trait that encapsulates protocol-specific note encryption types and logic.
@@ -134,6 +374,15 @@ pub trait Domain {
     type EphemeralPublicKey;
     type PreparedEphemeralPublicKey;
     type SharedSecret;
+    /// The key used to encrypt the note plaintext.
+    ///
+    /// This must be exactly [`SYMMETRIC_KEY_SIZE`] bytes long. Trial decryption with a
+    /// key of any other length fails, and encryption with one panics.
+    ///
+    /// This crate never wipes keys of this type itself, even with the `zeroize` feature
+    /// enabled. This includes the buffers of keys derived during batch trial decryption,
+    /// such as by [`batch::trial_phase`] and [`batch::try_note_decryption`]. Domains
+    /// that need their keys wiped should use a type that wipes itself on drop.
     type SymmetricKey: AsRef<[u8]>;
     type Note;
     type Recipient;
@@ -156,6 +405,16 @@ pub trait Domain {
     /// Extracts the `DiversifiedTransmissionKey` from the note.
     fn get_pk_d(note: &Self::Note) -> Self::DiversifiedTransmissionKey;
 
+    /// Extracts the `DiversifiedTransmissionKey` from the given recipient address.
+    ///
+    /// This is used by [`try_output_recovery_with_esk`]. The default implementation
+    /// returns `None`, indicating that the domain does not support that workflow.
+    fn pk_d_for_recipient(
+        _recipient: &Self::Recipient,
+    ) -> Option<Self::DiversifiedTransmissionKey> {
+        None
+    }
+
     /// Prepare an ephemeral public key for more efficient scalar multiplication.
     fn prepare_epk(epk: Self::EphemeralPublicKey) -> Self::PreparedEphemeralPublicKey;
 
@@ -303,6 +562,63 @@ pub trait BatchDomain: Domain {
             .collect()
     }
 
+    /// Computes `Self::kdf` on a batch of items, writing the results into `out`.
+    ///
+    /// This behaves like [`Self::batch_kdf`], but allows the caller to reuse a buffer
+    /// across batches instead of allocating a new one for each. Results are written to
+    /// `out` in order; if `items` and `out` have different lengths, the excess is ignored.
+    ///
+    /// This is used by the batch trial decryption APIs. The default implementation calls
+    /// [`Self::batch_kdf`] and moves the results into `out`, so domains that override
+    /// only [`Self::batch_kdf`] keep their batched logic; overriding this as well avoids
+    /// the intermediate allocation.
+    fn batch_kdf_into<'a>(
+        items: impl Iterator<Item = (Option<Self::SharedSecret>, &'a EphemeralKeyBytes)>,
+        out: &mut [Option<Self::SymmetricKey>],
+    ) {
+        for (slot, key) in out.iter_mut().zip(Self::batch_kdf(items)) {
+            *slot = key;
+        }
+    }
+
+    /// Checks the validity of a batch of decrypted notes against the `ephemeral_key` and
+    /// `cmstar_bytes` fields of the outputs they were decrypted from.
+    ///
+    /// Returns `true` at each position where the note's commitment matches
+    /// `cmstar_bytes` and, for notes created after [ZIP 212] activation, the ephemeral
+    /// key re-derived from the note matches `ephemeral_key`. This is used by the batch
+    /// trial decryption APIs for notes decrypted from full outputs, and can be overridden
+    /// to batch the ephemeral key derivation, which dominates the cost of validation when
+    /// many outputs decrypt successfully.
+    ///
+    /// Overrides must return exactly one result per item. If they do not, the results
+    /// are discarded and each note is checked individually.
+    ///
+    /// [ZIP 212]: https://zips.z.cash/zip-0212
+    fn batch_validate_notes<'a>(
+        items: impl Iterator<
+            Item = (
+                &'a Self::Note,
+                &'a EphemeralKeyBytes,
+                &'a Self::ExtractedCommitmentBytes,
+            ),
+        >,
+    ) -> Vec<bool>
+    where
+        Self::Note: 'a,
+        Self::ExtractedCommitmentBytes: 'a,
+    {
+        // Default implementation: do the non-batched thing.
+        items
+            .map(|(note, ephemeral_key, cmstar_bytes)| {
+                matches!(
+                    check_note_validity::<Self>(note, ephemeral_key, cmstar_bytes),
+                    NoteValidity::Valid
+                )
+            })
+            .collect()
+    }
+
     /// Computes `Self::epk` on a batch of ephemeral keys.
     ///
     /// This is useful for protocols where the underlying curve requires an inversion to
@@ -339,6 +655,139 @@ pub trait ShieldedOutput<D: Domain, const CIPHERTEXT_SIZE: usize> {
 
     /// Exposes the note ciphertext of the output.
     fn enc_ciphertext(&self) -> &[u8; CIPHERTEXT_SIZE];
+
+    /// Returns a short summary of the output, suitable for log lines.
+    ///
+    /// The summary contains prefixes of the ephemeral key and extracted note commitment
+    /// (which are public), and the length of the note ciphertext. It can be used to
+    /// correlate an output with its on-chain counterpart without logging the full
+    /// ciphertext.
+    fn summary(&self) -> OutputSummary
+    where
+        D::ExtractedCommitmentBytes: AsRef<[u8]>,
+    {
+        OutputSummary::new(
+            &self.ephemeral_key().0,
+            self.cmstar_bytes().as_ref(),
+            CIPHERTEXT_SIZE,
+        )
+    }
+}
+
+/// A short summary of a [`ShieldedOutput`], returned by [`ShieldedOutput::summary`].
+///
+/// The [`Display`](fmt::Display) implementation prints the first bytes of the
+/// ephemeral key and extracted note commitment in hex, followed by the ciphertext
+/// length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputSummary {
+    ephemeral_key: [u8; OutputSummary::PREFIX_SIZE],
+    cmstar: [u8; OutputSummary::PREFIX_SIZE],
+    cmstar_len: usize,
+    ciphertext_len: usize,
+}
+
+impl OutputSummary {
+    const PREFIX_SIZE: usize = 4;
+
+    fn new(ephemeral_key: &[u8; 32], cmstar: &[u8], ciphertext_len: usize) -> Self {
+        let cmstar_len = cmstar.len().min(Self::PREFIX_SIZE);
+        let mut summary = OutputSummary {
+            ephemeral_key: [0; Self::PREFIX_SIZE],
+            cmstar: [0; Self::PREFIX_SIZE],
+            cmstar_len,
+            ciphertext_len,
+        };
+        summary
+            .ephemeral_key
+            .copy_from_slice(&ephemeral_key[..Self::PREFIX_SIZE]);
+        summary.cmstar[..cmstar_len].copy_from_slice(&cmstar[..cmstar_len]);
+        summary
+    }
+}
+
+impl fmt::Display for OutputSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "epk={}.. cmstar={}.. ciphertext_len={}",
+            HexFmt(&self.ephemeral_key),
+            HexFmt(&self.cmstar[..self.cmstar_len]),
+            self.ciphertext_len,
+        )
+    }
+}
+
+/// Trait that provides access to the components of a full transaction output that are
+/// needed for its recovery by the sender.
+pub trait RecoverableOutput<D: Domain>: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE> {
+    /// Exposes the `cv` field of the output.
+    fn cv(&self) -> &D::ValueCommitment;
+
+    /// Exposes the `out_ciphertext` field of the output.
+    fn out_ciphertext(&self) -> &[u8; OUT_CIPHERTEXT_SIZE];
+}
+
+/// Feeds the public fields of the given output into `state`, for computing a
+/// deduplication key.
+///
+/// The `ephemeral_key`, `cmstar_bytes`, and note ciphertext of the output are written
+/// to `state` in that order, with no additional framing. The result of
+/// [`Hasher::finish`](core::hash::Hasher::finish) is stable across processes and
+/// services as long as the same hasher (with the same keys, if any) is used.
+pub fn hash_output<D: Domain, Output, H: core::hash::Hasher, const CS: usize>(
+    output: &Output,
+    state: &mut H,
+) where
+    Output: ShieldedOutput<D, CS> + ?Sized,
+    D::ExtractedCommitmentBytes: AsRef<[u8]>,
+{
+    state.write(&output.ephemeral_key().0);
+    state.write(output.cmstar_bytes().as_ref());
+    state.write(output.enc_ciphertext());
+}
+
+/// A view of a full [`ShieldedOutput`] as a compact output.
+///
+/// This allows a full output to be passed to the compact trial decryption APIs without
+/// copying or re-encrypting its ciphertext; the compact ciphertext is borrowed from the
+/// prefix of the full ciphertext.
+pub struct CompactView<'a, Output: ?Sized>(pub &'a Output);
+
+impl<'a, D: Domain, Output> ShieldedOutput<D, COMPACT_NOTE_SIZE> for CompactView<'a, Output>
+where
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE> + ?Sized,
+{
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        self.0.ephemeral_key()
+    }
+
+    fn cmstar_bytes(&self) -> D::ExtractedCommitmentBytes {
+        self.0.cmstar_bytes()
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; COMPACT_NOTE_SIZE] {
+        self.0.enc_ciphertext()[..COMPACT_NOTE_SIZE]
+            .try_into()
+            .unwrap()
+    }
+}
+
+/// The way in which the ephemeral secret key for a [`NoteEncryption`] context is
+/// obtained.
+pub enum EskMode<D: Domain> {
+    /// The `EphemeralSecretKey` is derived from the note, as required by [ZIP 212].
+    ///
+    /// [ZIP 212]: https://zips.z.cash/zip-0212
+    Zip212Enforced,
+    /// The given `EphemeralSecretKey` is used.
+    ///
+    /// This is only correct for notes created prior to [ZIP 212], and should only be used
+    /// to generate pre-ZIP-212 ciphertexts (for example, so that tests can continue to
+    /// cover pre-ZIP-212 transaction decryption).
+    ///
+    /// [ZIP 212]: https://zips.z.cash/zip-0212
+    LegacyExplicit(D::EphemeralSecretKey),
 }
 
 /// A struct containing context required for encrypting Sapling and Orchard notes.
@@ -361,20 +810,24 @@ pub struct NoteEncryption<D: Domain> {
 impl<D: Domain> NoteEncryption<D> {
     /// Construct a new note encryption context for the specified note,
     /// recipient, and memo.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the note was created prior to [ZIP 212], and so doesn't have a
+    /// deterministic `EphemeralSecretKey`. Use [`NoteEncryption::with_esk_mode`] to
+    /// handle this case without panicking.
+    ///
+    /// [ZIP 212]: https://zips.z.cash/zip-0212
     pub fn new(ovk: Option<D::OutgoingViewingKey>, note: D::Note, memo: D::Memo) -> Self {
-        let esk = D::derive_esk(&note).expect("ZIP 212 is active.");
-        NoteEncryption {
-            epk: D::ka_derive_public(&note, &esk),
-            esk,
-            note,
-            memo,
-            ovk,
-        }
+        Self::with_esk_mode(EskMode::Zip212Enforced, ovk, note, memo).expect("ZIP 212 is active.")
     }
 
     /// For use only with Sapling. This method is preserved in order that test code
     /// be able to generate pre-ZIP-212 ciphertexts so that tests can continue to
     /// cover pre-ZIP-212 transaction decryption.
+    ///
+    /// This is equivalent to [`NoteEncryption::with_esk_mode`] with
+    /// [`EskMode::LegacyExplicit`].
     #[cfg(feature = "pre-zip-212")]
     #[cfg_attr(docsrs, doc(cfg(feature = "pre-zip-212")))]
     pub fn new_with_esk(
@@ -382,6 +835,87 @@ impl<D: Domain> NoteEncryption<D> {
         ovk: Option<D::OutgoingViewingKey>,
         note: D::Note,
         memo: D::Memo,
+    ) -> Self {
+        Self::from_esk(esk, ovk, note, memo)
+    }
+
+    /// Construct a new note encryption context for the specified note,
+    /// recipient, and memo, obtaining the ephemeral secret key as specified by
+    /// `esk_mode`.
+    ///
+    /// Returns `None` if `esk_mode` is [`EskMode::Zip212Enforced`] and the note was
+    /// created prior to [ZIP 212], and so doesn't have a deterministic
+    /// `EphemeralSecretKey`.
+    ///
+    /// [ZIP 212]: https://zips.z.cash/zip-0212
+    pub fn with_esk_mode(
+        esk_mode: EskMode<D>,
+        ovk: Option<D::OutgoingViewingKey>,
+        note: D::Note,
+        memo: D::Memo,
+    ) -> Option<Self> {
+        let esk = match esk_mode {
+            EskMode::Zip212Enforced => D::derive_esk(&note)?,
+            EskMode::LegacyExplicit(esk) => esk,
+        };
+        Some(Self::from_esk(esk, ovk, note, memo))
+    }
+
+    /// Construct a new note encryption context for the specified note, recipient, and
+    /// memo, using an ephemeral key pair that was derived externally (for example, by a
+    /// hardware signing device).
+    ///
+    /// Returns `None` if `epk` is not the ephemeral public key derived from `esk` and
+    /// the note's diversifier. Use [`NoteEncryption::from_parts_unchecked`] to skip this
+    /// check.
+    ///
+    /// This does not check that `esk` is the ephemeral secret key derived from the note
+    /// as required by [ZIP 212]; the caller is responsible for ensuring this.
+    ///
+    /// [ZIP 212]: https://zips.z.cash/zip-0212
+    pub fn from_parts(
+        esk: D::EphemeralSecretKey,
+        epk: D::EphemeralPublicKey,
+        ovk: Option<D::OutgoingViewingKey>,
+        note: D::Note,
+        memo: D::Memo,
+    ) -> Option<Self> {
+        let expected = D::epk_bytes(&D::ka_derive_public(&note, &esk));
+        if D::epk_bytes(&epk).ct_eq(&expected).into() {
+            Some(Self::from_parts_unchecked(esk, epk, ovk, note, memo))
+        } else {
+            None
+        }
+    }
+
+    /// Construct a new note encryption context for the specified note, recipient, and
+    /// memo, using an ephemeral key pair that was derived externally (for example, by a
+    /// hardware signing device).
+    ///
+    /// Unlike [`NoteEncryption::from_parts`], this does not check that `epk` is
+    /// consistent with `esk`. If it is not, the resulting ciphertexts will not be
+    /// decryptable by the recipient.
+    pub fn from_parts_unchecked(
+        esk: D::EphemeralSecretKey,
+        epk: D::EphemeralPublicKey,
+        ovk: Option<D::OutgoingViewingKey>,
+        note: D::Note,
+        memo: D::Memo,
+    ) -> Self {
+        NoteEncryption {
+            epk,
+            esk,
+            note,
+            memo,
+            ovk,
+        }
+    }
+
+    fn from_esk(
+        esk: D::EphemeralSecretKey,
+        ovk: Option<D::OutgoingViewingKey>,
+        note: D::Note,
+        memo: D::Memo,
     ) -> Self {
         NoteEncryption {
             epk: D::ka_derive_public(&note, &esk),
@@ -404,22 +938,32 @@ impl<D: Domain> NoteEncryption<D> {
 
     /// Generates `encCiphertext` for this note.
     pub fn encrypt_note_plaintext(&self) -> [u8; ENC_CIPHERTEXT_SIZE] {
+        let pk_d = D::get_pk_d(&self.note);
+        let shared_secret = D::ka_agree_enc(&self.esk, &pk_d);
+        encrypt_note_plaintext_with_shared_secret::<D>(
+            shared_secret,
+            &D::epk_bytes(&self.epk),
+            &self.note,
+            &self.memo,
+        )
+    }
+
+    /// Generates the compact note ciphertext for this note, as served to light clients.
+    ///
+    /// This is equal to the first [`COMPACT_NOTE_SIZE`] bytes of
+    /// [`Self::encrypt_note_plaintext`], but avoids encrypting the memo.
+    pub fn encrypt_compact_note_plaintext(&self) -> [u8; COMPACT_NOTE_SIZE] {
         let pk_d = D::get_pk_d(&self.note);
         let shared_secret = D::ka_agree_enc(&self.esk, &pk_d);
         let key = D::kdf(shared_secret, &D::epk_bytes(&self.epk));
+        let key = symmetric_key::<D>(&key).expect("SymmetricKey must be 32 bytes");
         let input = D::note_plaintext_bytes(&self.note, &self.memo);
 
-        let mut output = [0u8; ENC_CIPHERTEXT_SIZE];
-        output[..NOTE_PLAINTEXT_SIZE].copy_from_slice(&input.0);
-        let tag = ChaCha20Poly1305::new(key.as_ref().into())
-            .encrypt_in_place_detached(
-                [0u8; 12][..].into(),
-                &[],
-                &mut output[..NOTE_PLAINTEXT_SIZE],
-            )
-            .unwrap();
-        output[NOTE_PLAINTEXT_SIZE..].copy_from_slice(&tag);
-
+        // Start from block 1 to skip over Poly1305 keying output
+        let mut output: [u8; COMPACT_NOTE_SIZE] = input.0[..COMPACT_NOTE_SIZE].try_into().unwrap();
+        let mut keystream = ChaCha20::new(key[..].into(), [0u8; NONCE_SIZE][..].into());
+        keystream.seek(CHACHA_POLY_KEY_BLOCK);
+        keystream.apply_keystream(&mut output);
         output
     }
 
@@ -438,78 +982,250 @@ impl<D: Domain> NoteEncryption<D> {
         } else {
             // ovk = ⊥
             let mut ock = OutgoingCipherKey([0; 32]);
-            let mut input = [0u8; OUT_PLAINTEXT_SIZE];
+            let mut input = OutPlaintextBytes([0; OUT_PLAINTEXT_SIZE]);
 
             rng.fill_bytes(&mut ock.0);
-            rng.fill_bytes(&mut input);
+            rng.fill_bytes(&mut input.0);
 
-            (ock, OutPlaintextBytes(input))
+            (ock, input)
         };
 
-        let mut output = [0u8; OUT_CIPHERTEXT_SIZE];
-        output[..OUT_PLAINTEXT_SIZE].copy_from_slice(&input.0);
-        let tag = ChaCha20Poly1305::new(ock.as_ref().into())
-            .encrypt_in_place_detached([0u8; 12][..].into(), &[], &mut output[..OUT_PLAINTEXT_SIZE])
-            .unwrap();
-        output[OUT_PLAINTEXT_SIZE..].copy_from_slice(&tag);
+        encrypt_outgoing_plaintext_with_ock(&ock, &input)
+    }
 
-        output
+    /// Writes `ephemeralKey ‖ encCiphertext ‖ outCiphertext` for this note to `writer`.
+    ///
+    /// These fields are contiguous in the encodings of both Sapling and Orchard outputs,
+    /// so this allows a transaction serializer to stream them directly into its output.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn write_ciphertexts<R: RngCore, W: std::io::Write>(
+        &self,
+        cv: &D::ValueCommitment,
+        cmstar: &D::ExtractedCommitment,
+        rng: &mut R,
+        mut writer: W,
+    ) -> std::io::Result<()> {
+        writer.write_all(&D::epk_bytes(&self.epk).0)?;
+        writer.write_all(&self.encrypt_note_plaintext())?;
+        writer.write_all(&self.encrypt_outgoing_plaintext(cv, cmstar, rng))
     }
 }
 
-/// Trial decryption of the full note plaintext by the recipient.
-///
-/// Attempts to decrypt and validate the given shielded output using the given `ivk`.
-/// If successful, the corresponding note and memo are returned, along with the address to
-/// which the note was sent.
+/// Generates `encCiphertext` for the given note from an externally computed
+/// `SharedSecret`.
 ///
-/// Implements section 4.19.2 of the
-/// [Zcash Protocol Specification](https://zips.z.cash/protocol/nu5.pdf#decryptivk).
-pub fn try_note_decryption<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
-    domain: &D,
-    ivk: &D::IncomingViewingKey,
-    output: &Output,
-) -> Option<(D::Note, D::Recipient, D::Memo)> {
-    let ephemeral_key = output.ephemeral_key();
-
-    let epk = D::prepare_epk(D::epk(&ephemeral_key)?);
-    let shared_secret = D::ka_agree_dec(ivk, &epk);
-    let key = D::kdf(shared_secret, &ephemeral_key);
-
-    try_note_decryption_inner(domain, ivk, &ephemeral_key, output, &key)
+/// This is intended for senders that cannot use [`NoteEncryption`] because no single
+/// party holds the ephemeral secret key (for example, threshold or multiparty senders).
+/// `shared_secret` must be the result of [`Domain::ka_agree_enc`] for the note's
+/// `DiversifiedTransmissionKey`, and `ephemeral_key` the encoding of the corresponding
+/// `EphemeralPublicKey`.
+pub fn encrypt_note_plaintext_with_shared_secret<D: Domain>(
+    shared_secret: D::SharedSecret,
+    ephemeral_key: &EphemeralKeyBytes,
+    note: &D::Note,
+    memo: &D::Memo,
+) -> [u8; ENC_CIPHERTEXT_SIZE] {
+    let key = D::kdf(shared_secret, ephemeral_key);
+    encrypt_note_plaintext_with_key::<D>(&key, note, memo)
 }
 
-fn try_note_decryption_inner<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
-    domain: &D,
+/// Generates `encCiphertext` for the given note from an externally derived
+/// `SymmetricKey`.
+///
+/// This is intended for senders that cannot use [`NoteEncryption`] because no single
+/// party holds the ephemeral secret key (for example, threshold or multiparty senders).
+/// `key` must be the result of [`Domain::kdf`] applied to the shared secret and the
+/// encoding of the ephemeral public key.
+///
+/// # Panics
+///
+/// Panics if `key` is not [`SYMMETRIC_KEY_SIZE`] bytes long.
+pub fn encrypt_note_plaintext_with_key<D: Domain>(
+    key: &D::SymmetricKey,
+    note: &D::Note,
+    memo: &D::Memo,
+) -> [u8; ENC_CIPHERTEXT_SIZE] {
+    let key = symmetric_key::<D>(key).expect("SymmetricKey must be 32 bytes");
+    let input = D::note_plaintext_bytes(note, memo);
+
+    let mut output = [0u8; ENC_CIPHERTEXT_SIZE];
+    output[..NOTE_PLAINTEXT_SIZE].copy_from_slice(&input.0);
+    let tag = ChaCha20Poly1305::new(key[..].into())
+        .encrypt_in_place_detached(
+            [0u8; NONCE_SIZE][..].into(),
+            &[],
+            &mut output[..NOTE_PLAINTEXT_SIZE],
+        )
+        .unwrap();
+    output[NOTE_PLAINTEXT_SIZE..].copy_from_slice(&tag);
+
+    output
+}
+
+/// Returns the compact note ciphertext corresponding to the given `encCiphertext`.
+///
+/// The compact ciphertext is a prefix of the full ciphertext, so this does not require
+/// any keys.
+pub fn compact_note_ciphertext(
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+) -> [u8; COMPACT_NOTE_SIZE] {
+    enc_ciphertext[..COMPACT_NOTE_SIZE].try_into().unwrap()
+}
+
+/// Generates `outCiphertext` by encrypting the given outgoing plaintext with the given
+/// `ock`.
+///
+/// This is intended for senders that cannot use [`NoteEncryption`], and so must
+/// assemble the outgoing plaintext themselves (or, for the `ovk = ⊥` case, generate a
+/// random `ock` and outgoing plaintext).
+pub fn encrypt_outgoing_plaintext_with_ock(
+    ock: &OutgoingCipherKey,
+    out_plaintext: &OutPlaintextBytes,
+) -> [u8; OUT_CIPHERTEXT_SIZE] {
+    let mut output = [0u8; OUT_CIPHERTEXT_SIZE];
+    output[..OUT_PLAINTEXT_SIZE].copy_from_slice(&out_plaintext.0);
+    let tag = ChaCha20Poly1305::new(ock.as_ref().into())
+        .encrypt_in_place_detached(
+            [0u8; NONCE_SIZE][..].into(),
+            &[],
+            &mut output[..OUT_PLAINTEXT_SIZE],
+        )
+        .unwrap();
+    output[OUT_PLAINTEXT_SIZE..].copy_from_slice(&tag);
+
+    output
+}
+
+/// Trial decryption of the full note plaintext by the recipient.
+///
+/// Attempts to decrypt and validate the given shielded output using the given `ivk`.
+/// If successful, the corresponding note and memo are returned, along with the address to
+/// which the note was sent.
+///
+/// Implements section 4.19.2 of the
+/// [Zcash Protocol Specification](https://zips.z.cash/protocol/nu5.pdf#decryptivk).
+pub fn try_note_decryption<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Option<(D::Note, D::Recipient, D::Memo)> {
+    try_note_decryption_detailed(domain, ivk, output).ok()
+}
+
+/// Trial decryption of the full note plaintext by the recipient, using an already
+/// prepared ephemeral public key.
+///
+/// This behaves like [`try_note_decryption`], but skips parsing and preparing the
+/// output's `ephemeral_key`. This is useful when trying several `ivk`s against the same
+/// output, or when the ephemeral key has already been parsed by an earlier stage of a
+/// scanning pipeline.
+///
+/// `epk` must be the result of [`Domain::prepare_epk`] applied to the parsed
+/// [`ShieldedOutput::ephemeral_key`] of `output`; if it is not, decryption will fail.
+pub fn try_note_decryption_with_prepared_epk<
+    D: Domain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    epk: &D::PreparedEphemeralPublicKey,
+    output: &Output,
+) -> Option<(D::Note, D::Recipient, D::Memo)> {
+    let ephemeral_key = output.ephemeral_key();
+
+    let shared_secret = D::ka_agree_dec(ivk, epk);
+    let key = D::kdf(shared_secret, &ephemeral_key);
+
+    try_note_decryption_inner(domain, ivk, &ephemeral_key, output, &key).ok()
+}
+
+/// Decrypts and parses the note plaintext, without checking the validity of the note.
+#[cfg(feature = "alloc")]
+#[allow(clippy::type_complexity)]
+fn try_note_decryption_inner_unvalidated<
+    D: Domain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE> + ?Sized,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+    key: &D::SymmetricKey,
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
+    let plaintext = decrypt_note_ciphertext::<D>(key, output.enc_ciphertext())
+        .ok_or(DecryptionError::DecryptionFailed)?;
+
+    parse_note_plaintext_ivk(domain, ivk, &plaintext)
+}
+
+#[allow(clippy::type_complexity)]
+fn try_note_decryption_inner<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE> + ?Sized>(
+    domain: &D,
     ivk: &D::IncomingViewingKey,
     ephemeral_key: &EphemeralKeyBytes,
     output: &Output,
     key: &D::SymmetricKey,
-) -> Option<(D::Note, D::Recipient, D::Memo)> {
-    let enc_ciphertext = output.enc_ciphertext();
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
+    let plaintext = decrypt_note_ciphertext::<D>(key, output.enc_ciphertext())
+        .ok_or(DecryptionError::DecryptionFailed)?;
+
+    parse_and_validate_note_plaintext(domain, ivk, ephemeral_key, output, &plaintext)
+}
+
+/// Parses the given note plaintext, and checks the validity of the resulting note
+/// against the output.
+#[allow(clippy::type_complexity)]
+fn parse_and_validate_note_plaintext<
+    D: Domain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE> + ?Sized,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    ephemeral_key: &EphemeralKeyBytes,
+    output: &Output,
+    plaintext: &NotePlaintextBytes,
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
+    let (note, to, memo) = parse_note_plaintext_ivk(domain, ivk, plaintext)?;
+
+    check_note_validity::<D>(&note, ephemeral_key, &output.cmstar_bytes())
+        .into_result()
+        .map_err(DecryptionError::InvalidNote)?;
 
+    Ok((note, to, memo))
+}
+
+#[allow(clippy::type_complexity)]
+fn parse_note_plaintext_ivk<D: Domain>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    plaintext: &NotePlaintextBytes,
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
+    let (note, to) = domain
+        .parse_note_plaintext_without_memo_ivk(ivk, &plaintext.0)
+        .ok_or(DecryptionError::InvalidPlaintext)?;
+    let memo = domain.extract_memo(plaintext);
+
+    Ok((note, to, memo))
+}
+
+fn decrypt_note_ciphertext<D: Domain>(
+    key: &D::SymmetricKey,
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+) -> Option<NotePlaintextBytes> {
+    let key = symmetric_key::<D>(key)?;
     let mut plaintext =
         NotePlaintextBytes(enc_ciphertext[..NOTE_PLAINTEXT_SIZE].try_into().unwrap());
 
-    ChaCha20Poly1305::new(key.as_ref().into())
+    ChaCha20Poly1305::new(key[..].into())
         .decrypt_in_place_detached(
-            [0u8; 12][..].into(),
+            [0u8; NONCE_SIZE][..].into(),
             &[],
             &mut plaintext.0,
             enc_ciphertext[NOTE_PLAINTEXT_SIZE..].into(),
         )
         .ok()?;
 
-    let (note, to) = parse_note_plaintext_without_memo_ivk(
-        domain,
-        ivk,
-        ephemeral_key,
-        &output.cmstar_bytes(),
-        &plaintext.0,
-    )?;
-    let memo = domain.extract_memo(&plaintext);
-
-    Some((note, to, memo))
+    Some(plaintext)
 }
 
 fn parse_note_plaintext_without_memo_ivk<D: Domain>(
@@ -528,7 +1244,27 @@ fn parse_note_plaintext_without_memo_ivk<D: Domain>(
     }
 }
 
-fn check_note_validity<D: Domain>(
+/// Checks that the given `esk` is consistent with the `esk` that can be derived from the
+/// given note.
+///
+/// This is the [ZIP 212] check performed during recovery of a note by its sender, which
+/// corresponds to `ToScalar(PRF^{expand}_{rseed}([4]) = esk` in
+/// [Zcash Protocol Specification section 4.19.3][decryptovk]. (`ρ^opt = []` for Sapling.)
+///
+/// Returns `Choice(1)` for notes created prior to ZIP 212, which do not have a
+/// deterministic `esk`.
+///
+/// [ZIP 212]: https://zips.z.cash/zip-0212
+/// [decryptovk]: https://zips.z.cash/protocol/nu5.pdf#decryptovk
+pub fn check_esk_consistency<D: Domain>(note: &D::Note, esk: &D::EphemeralSecretKey) -> Choice {
+    match D::derive_esk(note) {
+        Some(derived_esk) => derived_esk.ct_eq(esk),
+        // Before ZIP 212
+        None => Choice::from(1),
+    }
+}
+
+fn check_note_validity<D: Domain + ?Sized>(
     note: &D::Note,
     ephemeral_key: &EphemeralKeyBytes,
     cmstar_bytes: &D::ExtractedCommitmentBytes,
@@ -543,7 +1279,7 @@ fn check_note_validity<D: Domain>(
             {
                 NoteValidity::Valid
             } else {
-                NoteValidity::Invalid
+                NoteValidity::Invalid(NoteValidityError::EphemeralKeyMismatch)
             }
         } else {
             // Before ZIP 212
@@ -551,7 +1287,7 @@ fn check_note_validity<D: Domain>(
         }
     } else {
         // Published commitment doesn't match calculated commitment
-        NoteValidity::Invalid
+        NoteValidity::Invalid(NoteValidityError::CommitmentMismatch)
     }
 }
 
@@ -569,36 +1305,372 @@ pub fn try_compact_note_decryption<D: Domain, Output: ShieldedOutput<D, COMPACT_
     ivk: &D::IncomingViewingKey,
     output: &Output,
 ) -> Option<(D::Note, D::Recipient)> {
+    try_compact_note_decryption_detailed(domain, ivk, output).ok()
+}
+
+/// Trial decryption of the full note plaintext by the recipient, under each of several
+/// candidate domain contexts.
+///
+/// This is useful for archival data where the context of an output (for example,
+/// whether it was created before or after a consensus rule change) is unknown. The
+/// symmetric key does not depend on the domain context, so it is derived and the
+/// ciphertext is decrypted only once; only parsing and validating the note plaintext
+/// are repeated for each context.
+///
+/// Returns the index in `domains` of the first context under which the output was
+/// successfully decrypted, along with the decrypted note, recipient, and memo.
+#[allow(clippy::type_complexity)]
+pub fn try_note_decryption_with_domains<
+    D: Domain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    domains: &[D],
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Option<(usize, (D::Note, D::Recipient, D::Memo))> {
     let ephemeral_key = output.ephemeral_key();
 
     let epk = D::prepare_epk(D::epk(&ephemeral_key)?);
     let shared_secret = D::ka_agree_dec(ivk, &epk);
     let key = D::kdf(shared_secret, &ephemeral_key);
+    let plaintext = decrypt_note_ciphertext::<D>(&key, output.enc_ciphertext())?;
 
-    try_compact_note_decryption_inner(domain, ivk, &ephemeral_key, output, &key)
+    domains.iter().enumerate().find_map(|(i, domain)| {
+        parse_and_validate_note_plaintext(domain, ivk, &ephemeral_key, output, &plaintext)
+            .ok()
+            .map(|res| (i, res))
+    })
+}
+
+/// Trial decryption of the compact note plaintext by the recipient for light clients,
+/// under each of several candidate domain contexts.
+///
+/// See [`try_note_decryption_with_domains`] for details.
+#[allow(clippy::type_complexity)]
+pub fn try_compact_note_decryption_with_domains<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    domains: &[D],
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Option<(usize, (D::Note, D::Recipient))> {
+    let ephemeral_key = output.ephemeral_key();
+
+    let epk = D::prepare_epk(D::epk(&ephemeral_key)?);
+    let shared_secret = D::ka_agree_dec(ivk, &epk);
+    let key = D::kdf(shared_secret, &ephemeral_key);
+    let mut plaintext = decrypt_compact_note_ciphertext::<D>(&key, output.enc_ciphertext())?;
+
+    let cmstar_bytes = output.cmstar_bytes();
+    let result = domains.iter().enumerate().find_map(|(i, domain)| {
+        parse_note_plaintext_without_memo_ivk(
+            domain,
+            ivk,
+            &ephemeral_key,
+            &cmstar_bytes,
+            &plaintext,
+        )
+        .map(|res| (i, res))
+    });
+    wipe(&mut plaintext);
+    result
 }
 
-fn try_compact_note_decryption_inner<D: Domain, Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>>(
+/// Trial decryption of the compact note plaintext by the recipient for light clients,
+/// using an already prepared ephemeral public key.
+///
+/// This behaves like [`try_compact_note_decryption`], but skips parsing and preparing
+/// the output's `ephemeral_key`. See [`try_note_decryption_with_prepared_epk`] for the
+/// requirements on `epk`.
+pub fn try_compact_note_decryption_with_prepared_epk<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    epk: &D::PreparedEphemeralPublicKey,
+    output: &Output,
+) -> Option<(D::Note, D::Recipient)> {
+    let ephemeral_key = output.ephemeral_key();
+
+    let shared_secret = D::ka_agree_dec(ivk, epk);
+    let key = D::kdf(shared_secret, &ephemeral_key);
+
+    try_compact_note_decryption_inner(domain, ivk, &ephemeral_key, output, &key).ok()
+}
+
+fn try_compact_note_decryption_inner<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE> + ?Sized,
+>(
     domain: &D,
     ivk: &D::IncomingViewingKey,
     ephemeral_key: &EphemeralKeyBytes,
     output: &Output,
     key: &D::SymmetricKey,
-) -> Option<(D::Note, D::Recipient)> {
+) -> Result<(D::Note, D::Recipient), DecryptionError> {
+    let (note, to, mut plaintext) =
+        try_compact_note_decryption_inner_with_plaintext(domain, ivk, ephemeral_key, output, key)?;
+    wipe(&mut plaintext);
+
+    Ok((note, to))
+}
+
+/// Decrypts, parses and validates the compact note plaintext, returning it alongside the
+/// note. The plaintext is wiped before any error is returned.
+#[allow(clippy::type_complexity)]
+fn try_compact_note_decryption_inner_with_plaintext<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE> + ?Sized,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    ephemeral_key: &EphemeralKeyBytes,
+    output: &Output,
+    key: &D::SymmetricKey,
+) -> Result<(D::Note, D::Recipient, [u8; COMPACT_NOTE_SIZE]), DecryptionError> {
+    let mut plaintext = decrypt_compact_note_ciphertext::<D>(key, output.enc_ciphertext())
+        .ok_or(DecryptionError::DecryptionFailed)?;
+
+    // Compact ciphertexts are not authenticated, so for most (ivk, output) pairs the
+    // plaintext will fail to parse. Only fetch `cmstar_bytes` (which may require a
+    // conversion) once we have a note to check it against.
+    let (note, to) = match domain.parse_note_plaintext_without_memo_ivk(ivk, &plaintext) {
+        Some(parsed) => parsed,
+        None => {
+            wipe(&mut plaintext);
+            return Err(DecryptionError::InvalidPlaintext);
+        }
+    };
+
+    if let Err(e) =
+        check_note_validity::<D>(&note, ephemeral_key, &output.cmstar_bytes()).into_result()
+    {
+        wipe(&mut plaintext);
+        return Err(DecryptionError::InvalidNote(e));
+    }
+
+    Ok((note, to, plaintext))
+}
+
+fn decrypt_compact_note_ciphertext<D: Domain>(
+    key: &D::SymmetricKey,
+    enc_ciphertext: &[u8; COMPACT_NOTE_SIZE],
+) -> Option<[u8; COMPACT_NOTE_SIZE]> {
+    let key = symmetric_key::<D>(key)?;
+
     // Start from block 1 to skip over Poly1305 keying output
-    let mut plaintext = [0; COMPACT_NOTE_SIZE];
-    plaintext.copy_from_slice(output.enc_ciphertext());
-    let mut keystream = ChaCha20::new(key.as_ref().into(), [0u8; 12][..].into());
-    keystream.seek(64);
+    let mut plaintext = *enc_ciphertext;
+    let mut keystream = ChaCha20::new(key[..].into(), [0u8; NONCE_SIZE][..].into());
+    keystream.seek(CHACHA_POLY_KEY_BLOCK);
     keystream.apply_keystream(&mut plaintext);
+    Some(plaintext)
+}
+
+/// Returns `key` as a ChaCha20 key, or `None` if it is the wrong length.
+fn symmetric_key<D: Domain>(key: &D::SymmetricKey) -> Option<&[u8; SYMMETRIC_KEY_SIZE]> {
+    key.as_ref().try_into().ok()
+}
+
+/// Trial decryption of the full note plaintext by the recipient, reporting the reason
+/// for failure.
+///
+/// This behaves like [`try_note_decryption`], except that failures are described by a
+/// [`DecryptionError`].
+#[allow(clippy::type_complexity)]
+pub fn try_note_decryption_detailed<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
+    let ephemeral_key = output.ephemeral_key();
+
+    let epk = D::prepare_epk(D::epk(&ephemeral_key).ok_or(DecryptionError::InvalidEphemeralKey)?);
+    let shared_secret = D::ka_agree_dec(ivk, &epk);
+    let key = D::kdf(shared_secret, &ephemeral_key);
+
+    try_note_decryption_inner(domain, ivk, &ephemeral_key, output, &key)
+}
 
-    parse_note_plaintext_without_memo_ivk(
-        domain,
-        ivk,
+/// Trial decryption of the full note plaintext by the recipient, reporting why a
+/// decrypted note was rejected.
+///
+/// This behaves like [`try_note_decryption`], except that if the note ciphertext was
+/// successfully decrypted and parsed but the resulting note failed validation, the
+/// reason is returned as an error. Outputs that cannot be decrypted with `ivk` (which is
+/// the expected result for outputs not sent to `ivk`) result in `Ok(None)`.
+///
+/// This is useful when debugging [`Domain`] implementations against test vectors.
+#[allow(clippy::type_complexity)]
+pub fn try_note_decryption_diagnostic<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Result<Option<(D::Note, D::Recipient, D::Memo)>, NoteValidityError> {
+    match try_note_decryption_detailed(domain, ivk, output) {
+        Ok(res) => Ok(Some(res)),
+        Err(DecryptionError::InvalidNote(e)) => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Trial decryption of the compact note plaintext by the recipient for light clients,
+/// reporting the reason for failure.
+///
+/// This behaves like [`try_compact_note_decryption`], except that failures are
+/// described by a [`DecryptionError`].
+pub fn try_compact_note_decryption_detailed<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Result<(D::Note, D::Recipient), DecryptionError> {
+    let ephemeral_key = output.ephemeral_key();
+
+    let epk = D::prepare_epk(D::epk(&ephemeral_key).ok_or(DecryptionError::InvalidEphemeralKey)?);
+    let shared_secret = D::ka_agree_dec(ivk, &epk);
+    let key = D::kdf(shared_secret, &ephemeral_key);
+
+    try_compact_note_decryption_inner(domain, ivk, &ephemeral_key, output, &key)
+}
+
+/// Trial decryption of the compact note plaintext by the recipient for light clients,
+/// reporting why a decrypted note was rejected.
+///
+/// This behaves like [`try_compact_note_decryption`], except that if the compact note
+/// ciphertext was decrypted and parsed but the resulting note failed validation, the
+/// reason is returned as an error. Outputs that cannot be decrypted with `ivk` (which is
+/// the expected result for outputs not sent to `ivk`) result in `Ok(None)`.
+///
+/// This is useful when debugging [`Domain`] implementations against test vectors.
+#[allow(clippy::type_complexity)]
+pub fn try_compact_note_decryption_diagnostic<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Result<Option<(D::Note, D::Recipient)>, NoteValidityError> {
+    match try_compact_note_decryption_detailed(domain, ivk, output) {
+        Ok(res) => Ok(Some(res)),
+        Err(DecryptionError::InvalidNote(e)) => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Trial decryption of the compact note plaintext by the recipient for light clients,
+/// retaining the ability to decrypt the memo later.
+///
+/// This behaves like [`try_compact_note_decryption`], but additionally returns a
+/// [`MemoHandle`] that can be used to decrypt the memo from the corresponding full output
+/// once it has been fetched, without repeating the key agreement.
+pub fn try_compact_note_decryption_with_memo_handle<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Option<(D::Note, D::Recipient, MemoHandle<D>)> {
+    let ephemeral_key = output.ephemeral_key();
+
+    let epk = D::prepare_epk(D::epk(&ephemeral_key)?);
+    let shared_secret = D::ka_agree_dec(ivk, &epk);
+    let key = D::kdf(shared_secret, &ephemeral_key);
+
+    let (note, to) =
+        try_compact_note_decryption_inner(domain, ivk, &ephemeral_key, output, &key).ok()?;
+    let handle = MemoHandle {
+        key,
         ephemeral_key,
-        &output.cmstar_bytes(),
-        &plaintext,
-    )
+        cmstar_bytes: output.cmstar_bytes(),
+    };
+
+    Some((note, to, handle))
+}
+
+/// The result of successful compact trial decryption via
+/// [`try_compact_note_decryption_with_plaintext`].
+pub struct CompactDecryptionResult<D: Domain> {
+    /// The decrypted note.
+    pub note: D::Note,
+    /// The address to which the note was sent.
+    pub recipient: D::Recipient,
+    /// The decrypted compact note plaintext from which `note` and `recipient` were
+    /// parsed.
+    pub plaintext: [u8; COMPACT_NOTE_SIZE],
+}
+
+/// Trial decryption of the compact note plaintext by the recipient for light clients,
+/// returning the decrypted plaintext alongside the parsed note.
+///
+/// This behaves like [`try_compact_note_decryption`], but additionally returns the raw
+/// compact note plaintext, for callers that need to serve it to others without
+/// re-encoding the note.
+pub fn try_compact_note_decryption_with_plaintext<
+    D: Domain,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Option<CompactDecryptionResult<D>> {
+    let ephemeral_key = output.ephemeral_key();
+
+    let epk = D::prepare_epk(D::epk(&ephemeral_key)?);
+    let shared_secret = D::ka_agree_dec(ivk, &epk);
+    let key = D::kdf(shared_secret, &ephemeral_key);
+
+    let (note, recipient, plaintext) =
+        try_compact_note_decryption_inner_with_plaintext(domain, ivk, &ephemeral_key, output, &key)
+            .ok()?;
+    Some(CompactDecryptionResult {
+        note,
+        recipient,
+        plaintext,
+    })
+}
+
+/// A handle for decrypting the memo of a note found via compact trial decryption.
+///
+/// This is returned by [`try_compact_note_decryption_with_memo_handle`]. It holds the
+/// symmetric key derived during trial decryption, along with the `ephemeral_key` and
+/// `cmstar_bytes` fields identifying the output it was derived for.
+///
+/// The symmetric key allows the full note plaintext to be decrypted, so this handle
+/// must be treated as secret.
+pub struct MemoHandle<D: Domain> {
+    key: D::SymmetricKey,
+    ephemeral_key: EphemeralKeyBytes,
+    cmstar_bytes: D::ExtractedCommitmentBytes,
+}
+
+impl<D: Domain> MemoHandle<D> {
+    /// Decrypts the memo from the full output corresponding to this handle.
+    ///
+    /// The full note ciphertext is authenticated before the memo is extracted, so this
+    /// performs the AEAD decryption but not the key agreement.
+    ///
+    /// Returns `None` if `output` is not the output from which this handle was obtained,
+    /// or if its note ciphertext cannot be decrypted with the retained key.
+    pub fn decrypt<Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
+        &self,
+        domain: &D,
+        output: &Output,
+    ) -> Option<D::Memo> {
+        if !(bool::from(self.ephemeral_key.ct_eq(&output.ephemeral_key()))
+            && self.cmstar_bytes == output.cmstar_bytes())
+        {
+            return None;
+        }
+
+        let plaintext = decrypt_note_ciphertext::<D>(&self.key, output.enc_ciphertext())?;
+        Some(domain.extract_memo(&plaintext))
+    }
 }
 
 /// Recovery of the full note plaintext by the sender.
@@ -617,8 +1689,26 @@ pub fn try_output_recovery_with_ovk<D: Domain, Output: ShieldedOutput<D, ENC_CIP
     cv: &D::ValueCommitment,
     out_ciphertext: &[u8; OUT_CIPHERTEXT_SIZE],
 ) -> Option<(D::Note, D::Recipient, D::Memo)> {
+    try_output_recovery_with_ovk_detailed(domain, ovk, output, cv, out_ciphertext).ok()
+}
+
+/// Recovery of the full note plaintext by the sender, reporting the reason for failure.
+///
+/// This behaves like [`try_output_recovery_with_ovk`], except that failures are
+/// described by a [`DecryptionError`].
+#[allow(clippy::type_complexity)]
+pub fn try_output_recovery_with_ovk_detailed<
+    D: Domain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    domain: &D,
+    ovk: &D::OutgoingViewingKey,
+    output: &Output,
+    cv: &D::ValueCommitment,
+    out_ciphertext: &[u8; OUT_CIPHERTEXT_SIZE],
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
     let ock = D::derive_ock(ovk, cv, &output.cmstar_bytes(), &output.ephemeral_key());
-    try_output_recovery_with_ock(domain, &ock, output, out_ciphertext)
+    try_output_recovery_with_ock_detailed(domain, &ock, output, out_ciphertext)
 }
 
 /// Recovery of the full note plaintext by the sender.
@@ -636,21 +1726,79 @@ pub fn try_output_recovery_with_ock<D: Domain, Output: ShieldedOutput<D, ENC_CIP
     output: &Output,
     out_ciphertext: &[u8; OUT_CIPHERTEXT_SIZE],
 ) -> Option<(D::Note, D::Recipient, D::Memo)> {
+    try_output_recovery_with_ock_detailed(domain, ock, output, out_ciphertext).ok()
+}
+
+/// Recovery of the full note plaintext by the sender, reporting the reason for failure.
+///
+/// This behaves like [`try_output_recovery_with_ock`], except that failures are
+/// described by a [`DecryptionError`].
+#[allow(clippy::type_complexity)]
+pub fn try_output_recovery_with_ock_detailed<
+    D: Domain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    domain: &D,
+    ock: &OutgoingCipherKey,
+    output: &Output,
+    out_ciphertext: &[u8; OUT_CIPHERTEXT_SIZE],
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
+    let op =
+        decrypt_out_ciphertext(ock, out_ciphertext).ok_or(DecryptionError::DecryptionFailed)?;
+
+    let pk_d = D::extract_pk_d(&op).ok_or(DecryptionError::InvalidOutPlaintext)?;
+    let esk = D::extract_esk(&op).ok_or(DecryptionError::InvalidOutPlaintext)?;
+
+    try_output_recovery_with_pkd_esk_detailed(domain, pk_d, esk, output)
+}
+
+/// Checks that the given `out_ciphertext` can be decrypted with the given `ock`.
+///
+/// This only verifies the AEAD tag of `out_ciphertext`; the outgoing plaintext is not
+/// parsed, and the note is not recovered or validated. It is intended for confirming that
+/// an `ock` derivation is consistent with on-chain data, and is not a substitute for
+/// [`try_output_recovery_with_ock`].
+pub fn verify_out_ciphertext(
+    ock: &OutgoingCipherKey,
+    out_ciphertext: &[u8; OUT_CIPHERTEXT_SIZE],
+) -> bool {
+    decrypt_out_ciphertext(ock, out_ciphertext).is_some()
+}
+
+fn decrypt_out_ciphertext(
+    ock: &OutgoingCipherKey,
+    out_ciphertext: &[u8; OUT_CIPHERTEXT_SIZE],
+) -> Option<OutPlaintextBytes> {
     let mut op = OutPlaintextBytes([0; OUT_PLAINTEXT_SIZE]);
     op.0.copy_from_slice(&out_ciphertext[..OUT_PLAINTEXT_SIZE]);
 
     ChaCha20Poly1305::new(ock.as_ref().into())
         .decrypt_in_place_detached(
-            [0u8; 12][..].into(),
+            [0u8; NONCE_SIZE][..].into(),
             &[],
             &mut op.0,
             out_ciphertext[OUT_PLAINTEXT_SIZE..].into(),
         )
         .ok()?;
 
-    let pk_d = D::extract_pk_d(&op)?;
-    let esk = D::extract_esk(&op)?;
+    Some(op)
+}
 
+/// Recovery of the full note plaintext from a backed-up `esk`.
+///
+/// This supports wallets that retain the `esk` of each output they create (for example,
+/// for payment disclosure), allowing the note to be recovered from the output and the
+/// address it was sent to, without the `outCiphertext` or an outgoing viewing key.
+///
+/// Returns `None` if the domain does not implement [`Domain::pk_d_for_recipient`], or
+/// if [`try_output_recovery_with_pkd_esk`] fails.
+pub fn try_output_recovery_with_esk<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
+    domain: &D,
+    recipient: &D::Recipient,
+    esk: D::EphemeralSecretKey,
+    output: &Output,
+) -> Option<(D::Note, D::Recipient, D::Memo)> {
+    let pk_d = D::pk_d_for_recipient(recipient)?;
     try_output_recovery_with_pkd_esk(domain, pk_d, esk, output)
 }
 
@@ -672,6 +1820,23 @@ pub fn try_output_recovery_with_pkd_esk<
     esk: D::EphemeralSecretKey,
     output: &Output,
 ) -> Option<(D::Note, D::Recipient, D::Memo)> {
+    try_output_recovery_with_pkd_esk_detailed(domain, pk_d, esk, output).ok()
+}
+
+/// Recovery of the full note plaintext by the sender, reporting the reason for failure.
+///
+/// This behaves like [`try_output_recovery_with_pkd_esk`], except that failures are
+/// described by a [`DecryptionError`].
+#[allow(clippy::type_complexity)]
+pub fn try_output_recovery_with_pkd_esk_detailed<
+    D: Domain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    domain: &D,
+    pk_d: D::DiversifiedTransmissionKey,
+    esk: D::EphemeralSecretKey,
+    output: &Output,
+) -> Result<(D::Note, D::Recipient, D::Memo), DecryptionError> {
     let ephemeral_key = output.ephemeral_key();
     let shared_secret = D::ka_agree_enc(&esk, &pk_d);
     // The small-order point check at the point of output parsing rejects
@@ -679,38 +1844,747 @@ pub fn try_output_recovery_with_pkd_esk<
     // be okay.
     let key = D::kdf(shared_secret, &ephemeral_key);
 
-    let enc_ciphertext = output.enc_ciphertext();
-    let mut plaintext = NotePlaintextBytes([0; NOTE_PLAINTEXT_SIZE]);
-    plaintext
-        .0
-        .copy_from_slice(&enc_ciphertext[..NOTE_PLAINTEXT_SIZE]);
+    let plaintext = decrypt_note_ciphertext::<D>(&key, output.enc_ciphertext())
+        .ok_or(DecryptionError::DecryptionFailed)?;
 
-    ChaCha20Poly1305::new(key.as_ref().into())
-        .decrypt_in_place_detached(
-            [0u8; 12][..].into(),
-            &[],
-            &mut plaintext.0,
-            enc_ciphertext[NOTE_PLAINTEXT_SIZE..].into(),
+    let (note, to) = domain
+        .parse_note_plaintext_without_memo_ovk(&pk_d, &plaintext)
+        .ok_or(DecryptionError::InvalidPlaintext)?;
+    let memo = domain.extract_memo(&plaintext);
+
+    if (!check_esk_consistency::<D>(&note, &esk)).into() {
+        return Err(DecryptionError::InconsistentEsk);
+    }
+
+    check_note_validity::<D>(&note, &ephemeral_key, &output.cmstar_bytes())
+        .into_result()
+        .map_err(DecryptionError::InvalidNote)?;
+
+    Ok((note, to, memo))
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use alloc::{format, vec::Vec};
+    use core::hash::Hasher;
+    use subtle::{Choice, ConditionallySelectable};
+
+    use super::{
+        check_note_against_output, compact_keystream_range, encrypt_note_plaintext_with_key,
+        encrypt_note_plaintext_with_shared_secret, encrypt_outgoing_plaintext_with_ock,
+        hash_output, memo_keystream_range, try_compact_note_decryption,
+        try_compact_note_decryption_detailed, try_compact_note_decryption_diagnostic,
+        try_compact_note_decryption_with_domains, try_compact_note_decryption_with_memo_handle,
+        try_compact_note_decryption_with_plaintext, try_compact_note_decryption_with_prepared_epk,
+        try_note_decryption, try_note_decryption_detailed, try_note_decryption_diagnostic,
+        try_note_decryption_with_domains, try_note_decryption_with_prepared_epk,
+        try_output_recovery_with_esk, try_output_recovery_with_ock_detailed,
+        try_output_recovery_with_pkd_esk_detailed, verify_out_ciphertext, ChaCha20,
+        DecryptionError, Domain, EphemeralKeyBytes, EskMode, KeyIvInit, NoteEncryption,
+        NotePlaintextBytes, NoteValidityError, OutPlaintextBytes, OutgoingCipherKey,
+        ShieldedOutput, StreamCipher, StreamCipherSeek, COMPACT_NOTE_SIZE, NONCE_SIZE,
+        NOTE_PLAINTEXT_SIZE, OUT_CIPHERTEXT_SIZE, OUT_PLAINTEXT_SIZE,
+    };
+    use crate::test_domain::{
+        compact_output, full_output, sent_output, ContextDomain, MockCmx, MockDomain, MockNote,
+        MockOutput, INVALID_EPK, ZIP_212_VALUE,
+    };
+
+    #[cfg(feature = "std")]
+    use super::{try_output_recovery_with_ovk, ENC_CIPHERTEXT_SIZE};
+    #[cfg(feature = "std")]
+    use crate::test_domain::MockRng;
+
+    #[test]
+    fn keystream_ranges_decrypt_each_region() {
+        let key = [7; 32];
+        let note = MockNote {
+            ivk: 0b01,
+            value: 5,
+        };
+        let ciphertext = encrypt_note_plaintext_with_key::<MockDomain>(&key, &note, &());
+        let plaintext = MockDomain::note_plaintext_bytes(&note, &());
+
+        let regions = [
+            (compact_keystream_range(), 0..COMPACT_NOTE_SIZE),
+            (
+                memo_keystream_range(),
+                COMPACT_NOTE_SIZE..NOTE_PLAINTEXT_SIZE,
+            ),
+        ];
+        for (keystream_range, region) in regions {
+            assert_eq!(
+                (keystream_range.end - keystream_range.start) as usize,
+                region.len()
+            );
+            let mut keystream = ChaCha20::new(key[..].into(), [0u8; NONCE_SIZE][..].into());
+            keystream.seek(keystream_range.start);
+            let mut buf = ciphertext[region.clone()].to_vec();
+            keystream.apply_keystream(&mut buf);
+            assert_eq!(buf, plaintext.0[region]);
+        }
+    }
+
+    #[test]
+    fn conditional_selection() {
+        let a = EphemeralKeyBytes([1; 32]);
+        let b = EphemeralKeyBytes([2; 32]);
+        assert_eq!(
+            EphemeralKeyBytes::conditional_select(&a, &b, Choice::from(0)),
+            a
+        );
+        assert_eq!(
+            EphemeralKeyBytes::conditional_select(&a, &b, Choice::from(1)),
+            b
+        );
+
+        let mut note_plaintext = NotePlaintextBytes([1; NOTE_PLAINTEXT_SIZE]);
+        let other = NotePlaintextBytes([2; NOTE_PLAINTEXT_SIZE]);
+        note_plaintext.conditional_assign(&other, Choice::from(0));
+        assert_eq!(note_plaintext.0, [1; NOTE_PLAINTEXT_SIZE]);
+        note_plaintext.conditional_assign(&other, Choice::from(1));
+        assert_eq!(note_plaintext.0, [2; NOTE_PLAINTEXT_SIZE]);
+
+        let mut out_plaintext = OutPlaintextBytes([1; OUT_PLAINTEXT_SIZE]);
+        let other = OutPlaintextBytes([2; OUT_PLAINTEXT_SIZE]);
+        out_plaintext.conditional_assign(&other, Choice::from(0));
+        assert_eq!(out_plaintext.0, [1; OUT_PLAINTEXT_SIZE]);
+        out_plaintext.conditional_assign(&other, Choice::from(1));
+        assert_eq!(out_plaintext.0, [2; OUT_PLAINTEXT_SIZE]);
+    }
+
+    #[test]
+    fn output_summary_display() {
+        let full = full_output(0xab, 0b01, 1, MockCmx([0x12, 0x34]));
+        let compact = compact_output(0xab, 0b01, 1, MockCmx([0x12, 0x34]));
+        assert_eq!(
+            format!("{}", ShieldedOutput::<MockDomain, _>::summary(&full)),
+            "epk=abababab.. cmstar=1234.. ciphertext_len=580"
+        );
+        assert_eq!(
+            format!("{}", ShieldedOutput::<MockDomain, _>::summary(&compact)),
+            "epk=abababab.. cmstar=1234.. ciphertext_len=52"
+        );
+    }
+
+    /// A hasher that records the bytes written to it.
+    struct RecordingHasher(Vec<u8>);
+
+    impl Hasher for RecordingHasher {
+        fn finish(&self) -> u64 {
+            // FNV-1a.
+            self.0.iter().fold(0xcbf29ce484222325, |h, b| {
+                (h ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+            })
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.extend_from_slice(bytes);
+        }
+    }
+
+    #[test]
+    fn hash_output_covers_each_field() {
+        let hash = |output: &MockOutput<COMPACT_NOTE_SIZE>| {
+            let mut state = RecordingHasher(Vec::new());
+            hash_output::<MockDomain, _, _, COMPACT_NOTE_SIZE>(output, &mut state);
+            let digest = state.finish();
+            (state.0, digest)
+        };
+
+        let output = compact_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let (bytes, digest) = hash(&output);
+        let mut expected = output.ephemeral_key.0.to_vec();
+        expected.extend_from_slice(&output.cmx.0);
+        expected.extend_from_slice(&output.enc_ciphertext);
+        assert_eq!(bytes, expected);
+
+        let mut changed = [output.clone(), output.clone(), output.clone()];
+        changed[0].ephemeral_key.0[31] ^= 1;
+        changed[1].cmx.0[1] ^= 1;
+        changed[2].enc_ciphertext[COMPACT_NOTE_SIZE - 1] ^= 1;
+        for output in &changed {
+            assert_ne!(hash(output).1, digest);
+        }
+    }
+
+    #[test]
+    fn invalid_ephemeral_key() {
+        let cmx = MockCmx::for_note(0, 1);
+        assert_eq!(
+            try_note_decryption_detailed(&MockDomain, &0, &full_output(INVALID_EPK, 0b01, 1, cmx)),
+            Err(DecryptionError::InvalidEphemeralKey)
+        );
+        assert_eq!(
+            try_compact_note_decryption_detailed(
+                &MockDomain,
+                &0,
+                &compact_output(INVALID_EPK, 0b01, 1, cmx)
+            ),
+            Err(DecryptionError::InvalidEphemeralKey)
+        );
+    }
+
+    #[test]
+    fn decryption_failed() {
+        // The symmetric key is derived from the ephemeral key, so changing it after
+        // encryption makes the AEAD tag check fail.
+        let mut output = full_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        output.ephemeral_key = EphemeralKeyBytes([2; 32]);
+        assert_eq!(
+            try_note_decryption_detailed(&MockDomain, &0, &output),
+            Err(DecryptionError::DecryptionFailed)
+        );
+    }
+
+    #[test]
+    fn invalid_plaintext() {
+        let output = full_output(1, 0b10, 1, MockCmx::for_note(1, 1));
+        assert_eq!(
+            try_note_decryption_detailed(&MockDomain, &0, &output),
+            Err(DecryptionError::InvalidPlaintext)
+        );
+        assert!(try_note_decryption_detailed(&MockDomain, &1, &output).is_ok());
+    }
+
+    #[test]
+    fn invalid_note() {
+        let output = full_output(1, 0b01, 1, MockCmx::for_note(1, 1));
+        assert_eq!(
+            try_note_decryption_detailed(&MockDomain, &0, &output),
+            Err(DecryptionError::InvalidNote(
+                NoteValidityError::CommitmentMismatch
+            ))
+        );
+
+        let output = full_output(1, 0b01, ZIP_212_VALUE, MockCmx::for_note(0, ZIP_212_VALUE));
+        assert_eq!(
+            try_note_decryption_detailed(&MockDomain, &0, &output),
+            Err(DecryptionError::InvalidNote(
+                NoteValidityError::EphemeralKeyMismatch
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_out_plaintext() {
+        let output = full_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let ock = OutgoingCipherKey([0; 32]);
+
+        // An empty mask has no lowest IVK, so the mock cannot extract a `pk_d`.
+        let note = MockNote { ivk: 0, value: 1 };
+        let out_ciphertext = encrypt_outgoing_plaintext_with_ock(
+            &ock,
+            &MockDomain::outgoing_plaintext_bytes(&note, &1),
+        );
+        assert_eq!(
+            try_output_recovery_with_ock_detailed(&MockDomain, &ock, &output, &out_ciphertext),
+            Err(DecryptionError::InvalidOutPlaintext)
+        );
+
+        let note = MockNote {
+            ivk: 0b01,
+            value: 1,
+        };
+        let out_ciphertext = encrypt_outgoing_plaintext_with_ock(
+            &ock,
+            &MockDomain::outgoing_plaintext_bytes(&note, &1),
+        );
+        assert_eq!(
+            try_output_recovery_with_ock_detailed(&MockDomain, &ock, &output, &out_ciphertext),
+            Ok((MockNote { ivk: 0, value: 1 }, 0, ()))
+        );
+    }
+
+    #[test]
+    fn inconsistent_esk() {
+        let output = full_output(
+            ZIP_212_VALUE,
+            0b01,
+            ZIP_212_VALUE,
+            MockCmx::for_note(0, ZIP_212_VALUE),
+        );
+        assert_eq!(
+            try_output_recovery_with_pkd_esk_detailed(&MockDomain, 0, ZIP_212_VALUE + 1, &output),
+            Err(DecryptionError::InconsistentEsk)
+        );
+        assert_eq!(
+            try_output_recovery_with_pkd_esk_detailed(&MockDomain, 0, ZIP_212_VALUE, &output),
+            Ok((
+                MockNote {
+                    ivk: 0,
+                    value: ZIP_212_VALUE
+                },
+                0,
+                ()
+            ))
+        );
+    }
+
+    #[test]
+    fn recovery_with_esk() {
+        let sent = sent_output(
+            ZIP_212_VALUE,
+            0b01,
+            ZIP_212_VALUE,
+            MockCmx::for_note(0, ZIP_212_VALUE),
+        );
+        assert_eq!(
+            try_output_recovery_with_esk(&MockDomain, &0, ZIP_212_VALUE, &sent),
+            Some((
+                MockNote {
+                    ivk: 0,
+                    value: ZIP_212_VALUE
+                },
+                0,
+                ()
+            ))
+        );
+        assert_eq!(
+            try_output_recovery_with_esk(&MockDomain, &0, ZIP_212_VALUE + 1, &sent),
+            None
+        );
+
+        // `ContextDomain` does not implement `Domain::pk_d_for_recipient`.
+        assert_eq!(
+            try_output_recovery_with_esk(
+                &ContextDomain { max_value: u8::MAX },
+                &0,
+                ZIP_212_VALUE,
+                &sent.output
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn verify_out_ciphertext_checks_tag() {
+        let sent = sent_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let ock = MockDomain::derive_ock(&(), &(), &sent.output.cmx, &sent.output.ephemeral_key);
+        assert!(verify_out_ciphertext(&ock, &sent.out_ciphertext));
+
+        let mut out_ciphertext = sent.out_ciphertext;
+        out_ciphertext[OUT_CIPHERTEXT_SIZE - 1] ^= 1;
+        assert!(!verify_out_ciphertext(&ock, &out_ciphertext));
+
+        assert!(!verify_out_ciphertext(
+            &OutgoingCipherKey([1; 32]),
+            &sent.out_ciphertext
+        ));
+    }
+
+    #[test]
+    fn decryption_with_prepared_epk() {
+        let cases = [
+            // Decrypts to a valid note.
+            (1, 0b01, 1, MockCmx::for_note(0, 1), true),
+            // The plaintext cannot be parsed.
+            (1, 0b10, 1, MockCmx::for_note(1, 1), false),
+            // The note does not match the commitment.
+            (1, 0b01, 1, MockCmx::for_note(0, 2), false),
+        ];
+
+        for (ek, mask, value, cmx, decrypts) in cases {
+            let full = full_output(ek, mask, value, cmx);
+            let compact = compact_output(ek, mask, value, cmx);
+            let epk = MockDomain::prepare_epk(MockDomain::epk(&full.ephemeral_key).unwrap());
+
+            let expected = try_note_decryption(&MockDomain, &0, &full);
+            assert_eq!(expected.is_some(), decrypts);
+            assert_eq!(
+                try_note_decryption_with_prepared_epk(&MockDomain, &0, &epk, &full),
+                expected
+            );
+            assert_eq!(
+                try_compact_note_decryption_with_prepared_epk(&MockDomain, &0, &epk, &compact),
+                try_compact_note_decryption(&MockDomain, &0, &compact)
+            );
+        }
+    }
+
+    #[test]
+    fn note_checked_against_output() {
+        let note = MockNote { ivk: 0, value: 1 };
+        let output = full_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        assert_eq!(
+            check_note_against_output::<MockDomain, _, _>(&note, &output),
+            Ok(())
+        );
+        assert_eq!(
+            check_note_against_output::<MockDomain, _, _>(
+                &note,
+                &full_output(1, 0b01, 1, MockCmx::for_note(0, 2))
+            ),
+            Err(NoteValidityError::CommitmentMismatch)
+        );
+
+        // Post-ZIP 212 notes must also match the output's ephemeral key.
+        let note = MockNote {
+            ivk: 0,
+            value: ZIP_212_VALUE,
+        };
+        let cmx = MockCmx::for_note(0, ZIP_212_VALUE);
+        assert_eq!(
+            check_note_against_output::<MockDomain, _, _>(
+                &note,
+                &compact_output(ZIP_212_VALUE, 0b01, ZIP_212_VALUE, cmx)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check_note_against_output::<MockDomain, _, _>(
+                &note,
+                &compact_output(ZIP_212_VALUE + 1, 0b01, ZIP_212_VALUE, cmx)
+            ),
+            Err(NoteValidityError::EphemeralKeyMismatch)
+        );
+    }
+
+    #[test]
+    fn compact_decryption_with_plaintext() {
+        let outputs = [
+            compact_output(1, 0b01, 1, MockCmx::for_note(0, 1)),
+            compact_output(2, 0b10, 2, MockCmx::for_note(1, 2)),
+            compact_output(3, 0b01, 3, MockCmx::for_note(1, 3)),
+        ];
+        for output in &outputs {
+            let result = try_compact_note_decryption_with_plaintext(&MockDomain, &0, output);
+            assert_eq!(
+                result.as_ref().map(|r| (r.note, r.recipient)),
+                try_compact_note_decryption(&MockDomain, &0, output)
+            );
+        }
+
+        let result =
+            try_compact_note_decryption_with_plaintext(&MockDomain, &0, &outputs[0]).unwrap();
+        assert_eq!(&result.plaintext[..2], &[0b01, 1]);
+    }
+
+    #[test]
+    fn compact_ciphertext_is_prefix_of_full_ciphertext() {
+        for (mask, value) in [(0b01, 1), (0b10, 2), (0b11, ZIP_212_VALUE)] {
+            let note = MockNote { ivk: mask, value };
+            let ne = NoteEncryption::<MockDomain>::with_esk_mode(
+                EskMode::LegacyExplicit(7),
+                Some(()),
+                note,
+                (),
+            )
+            .unwrap();
+            assert_eq!(
+                ne.encrypt_compact_note_plaintext()[..],
+                ne.encrypt_note_plaintext()[..COMPACT_NOTE_SIZE]
+            );
+        }
+    }
+
+    #[test]
+    fn memo_handle_decrypts_matching_output() {
+        let full = full_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let compact = compact_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+
+        let (note, recipient, handle) =
+            try_compact_note_decryption_with_memo_handle(&MockDomain, &0, &compact).unwrap();
+        assert_eq!((note, recipient), (MockNote { ivk: 0, value: 1 }, 0));
+        assert_eq!(handle.decrypt(&MockDomain, &full), Some(()));
+
+        // The handle is bound to the output it was derived from.
+        let other = full_output(2, 0b01, 1, MockCmx::for_note(0, 1));
+        assert_eq!(handle.decrypt(&MockDomain, &other), None);
+        let mut other = full.clone();
+        other.cmx = MockCmx::for_note(0, 2);
+        assert_eq!(handle.decrypt(&MockDomain, &other), None);
+
+        // The full ciphertext is authenticated.
+        let mut tampered = full;
+        tampered.enc_ciphertext[COMPACT_NOTE_SIZE] ^= 1;
+        assert_eq!(handle.decrypt(&MockDomain, &tampered), None);
+
+        // No handle is returned for an output the IVK cannot decrypt.
+        let compact = compact_output(1, 0b10, 1, MockCmx::for_note(1, 1));
+        assert!(try_compact_note_decryption_with_memo_handle(&MockDomain, &0, &compact).is_none());
+    }
+
+    #[test]
+    fn esk_mode_round_trip() {
+        let pre_zip_212 = MockNote {
+            ivk: 0b01,
+            value: 1,
+        };
+        let post_zip_212 = MockNote {
+            ivk: 0b01,
+            value: ZIP_212_VALUE,
+        };
+
+        // Pre-ZIP 212 notes have no deterministic esk.
+        assert!(NoteEncryption::<MockDomain>::with_esk_mode(
+            EskMode::Zip212Enforced,
+            None,
+            pre_zip_212,
+            ()
         )
-        .ok()?;
+        .is_none());
+
+        for (esk_mode, note, esk) in [
+            (EskMode::Zip212Enforced, post_zip_212, ZIP_212_VALUE),
+            (EskMode::LegacyExplicit(7), pre_zip_212, 7),
+        ] {
+            let ne = NoteEncryption::<MockDomain>::with_esk_mode(esk_mode, None, note, ()).unwrap();
+            assert_eq!(*ne.esk(), esk);
+
+            let output = MockOutput {
+                ephemeral_key: MockDomain::epk_bytes(ne.epk()),
+                cmx: MockCmx::for_note(0, note.value),
+                enc_ciphertext: ne.encrypt_note_plaintext(),
+            };
+            assert_eq!(
+                try_note_decryption(&MockDomain, &0, &output),
+                Some((
+                    MockNote {
+                        ivk: 0,
+                        value: note.value
+                    },
+                    0,
+                    ()
+                ))
+            );
+        }
+    }
 
-    let (note, to) = domain.parse_note_plaintext_without_memo_ovk(&pk_d, &plaintext)?;
-    let memo = domain.extract_memo(&plaintext);
+    #[test]
+    fn note_encryption_from_parts() {
+        let note = MockNote {
+            ivk: 0b01,
+            value: ZIP_212_VALUE,
+        };
+        let esk = ZIP_212_VALUE;
+        let epk = EphemeralKeyBytes([ZIP_212_VALUE; 32]);
+        let mismatched_epk = EphemeralKeyBytes([ZIP_212_VALUE + 1; 32]);
+
+        // A matching key pair produces the same context as `NoteEncryption::new`.
+        let expected = NoteEncryption::<MockDomain>::new(None, note, ());
+        let ne = NoteEncryption::<MockDomain>::from_parts(esk, epk, None, note, ()).unwrap();
+        assert_eq!(ne.esk(), expected.esk());
+        assert_eq!(ne.epk(), expected.epk());
+        assert_eq!(
+            ne.encrypt_note_plaintext()[..],
+            expected.encrypt_note_plaintext()[..]
+        );
+
+        assert!(
+            NoteEncryption::<MockDomain>::from_parts(esk, mismatched_epk, None, note, ()).is_none()
+        );
+
+        // The unchecked constructor keeps the mismatched key, producing an output whose
+        // note the recipient rejects.
+        let ne =
+            NoteEncryption::<MockDomain>::from_parts_unchecked(esk, mismatched_epk, None, note, ());
+        assert_eq!(ne.esk(), &esk);
+        assert_eq!(ne.epk(), &mismatched_epk);
+        let output = MockOutput {
+            ephemeral_key: mismatched_epk,
+            cmx: MockCmx::for_note(0, ZIP_212_VALUE),
+            enc_ciphertext: ne.encrypt_note_plaintext(),
+        };
+        assert_eq!(
+            try_note_decryption_detailed(&MockDomain, &0, &output),
+            Err(DecryptionError::InvalidNote(
+                NoteValidityError::EphemeralKeyMismatch
+            ))
+        );
+    }
 
-    // ZIP 212: Check that the esk provided to this function is consistent with the esk we can
-    // derive from the note. This check corresponds to `ToScalar(PRF^{expand}_{rseed}([4]) = esk`
-    // in https://zips.z.cash/protocol/protocol.pdf#decryptovk. (`ρ^opt = []` for Sapling.)
-    if let Some(derived_esk) = D::derive_esk(&note) {
-        if (!derived_esk.ct_eq(&esk)).into() {
-            return None;
+    #[cfg(feature = "std")]
+    #[test]
+    fn write_ciphertexts_round_trip() {
+        let note = MockNote {
+            ivk: 0b01,
+            value: ZIP_212_VALUE,
+        };
+        let cmx = MockCmx::for_note(0, ZIP_212_VALUE);
+        let ne = NoteEncryption::<MockDomain>::new(Some(()), note, ());
+
+        let mut buf = std::vec::Vec::new();
+        ne.write_ciphertexts(&(), &cmx, &mut MockRng(0), &mut buf)
+            .unwrap();
+        assert_eq!(buf.len(), 32 + ENC_CIPHERTEXT_SIZE + OUT_CIPHERTEXT_SIZE);
+
+        let (ephemeral_key, rest) = buf.split_at(32);
+        let (enc_ciphertext, out_ciphertext) = rest.split_at(ENC_CIPHERTEXT_SIZE);
+        assert_eq!(ephemeral_key, &MockDomain::epk_bytes(ne.epk()).0[..]);
+        assert_eq!(enc_ciphertext, &ne.encrypt_note_plaintext()[..]);
+        assert_eq!(
+            out_ciphertext,
+            &ne.encrypt_outgoing_plaintext(&(), &cmx, &mut MockRng(0))[..]
+        );
+
+        let output = MockOutput {
+            ephemeral_key: EphemeralKeyBytes(ephemeral_key.try_into().unwrap()),
+            cmx,
+            enc_ciphertext: enc_ciphertext.try_into().unwrap(),
+        };
+        let expected = Some((
+            MockNote {
+                ivk: 0,
+                value: ZIP_212_VALUE,
+            },
+            0,
+            (),
+        ));
+        assert_eq!(try_note_decryption(&MockDomain, &0, &output), expected);
+        assert_eq!(
+            try_output_recovery_with_ovk(
+                &MockDomain,
+                &(),
+                &output,
+                &(),
+                out_ciphertext.try_into().unwrap()
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn encrypt_with_shared_secret_or_key() {
+        let note = MockNote {
+            ivk: 0b01,
+            value: 1,
+        };
+        let ne =
+            NoteEncryption::<MockDomain>::with_esk_mode(EskMode::LegacyExplicit(3), None, note, ())
+                .unwrap();
+        let ephemeral_key = MockDomain::epk_bytes(ne.epk());
+        #[allow(clippy::let_unit_value)]
+        let shared_secret = MockDomain::ka_agree_enc(ne.esk(), &MockDomain::get_pk_d(&note));
+        let key = MockDomain::kdf(shared_secret, &ephemeral_key);
+
+        let enc_ciphertext = ne.encrypt_note_plaintext();
+        assert_eq!(
+            encrypt_note_plaintext_with_shared_secret::<MockDomain>(
+                shared_secret,
+                &ephemeral_key,
+                &note,
+                &()
+            )[..],
+            enc_ciphertext[..]
+        );
+        assert_eq!(
+            encrypt_note_plaintext_with_key::<MockDomain>(&key, &note, &())[..],
+            enc_ciphertext[..]
+        );
+
+        let output = MockOutput {
+            ephemeral_key,
+            cmx: MockCmx::for_note(0, 1),
+            enc_ciphertext,
+        };
+        assert_eq!(
+            try_note_decryption(&MockDomain, &0, &output),
+            Some((MockNote { ivk: 0, value: 1 }, 0, ()))
+        );
+    }
+
+    #[test]
+    fn diagnostic_reports_rejected_notes() {
+        let cases = [
+            // Decrypts to a valid note.
+            (1, 0b01, 1, MockCmx::for_note(0, 1), Ok(Some(1))),
+            // Not decryptable by IVK 0.
+            (1, 0b10, 1, MockCmx::for_note(1, 1), Ok(None)),
+            (INVALID_EPK, 0b01, 1, MockCmx::for_note(0, 1), Ok(None)),
+            // Decrypts, but the note is invalid.
+            (
+                1,
+                0b01,
+                1,
+                MockCmx::for_note(1, 1),
+                Err(NoteValidityError::CommitmentMismatch),
+            ),
+            (
+                1,
+                0b01,
+                ZIP_212_VALUE,
+                MockCmx::for_note(0, ZIP_212_VALUE),
+                Err(NoteValidityError::EphemeralKeyMismatch),
+            ),
+        ];
+
+        for (ek, mask, value, cmx, expected) in cases {
+            assert_eq!(
+                try_note_decryption_diagnostic(&MockDomain, &0, &full_output(ek, mask, value, cmx))
+                    .map(|res| res.map(|(note, _, _)| note.value)),
+                expected
+            );
+            assert_eq!(
+                try_compact_note_decryption_diagnostic(
+                    &MockDomain,
+                    &0,
+                    &compact_output(ek, mask, value, cmx)
+                )
+                .map(|res| res.map(|(note, _)| note.value)),
+                expected
+            );
         }
     }
 
-    if let NoteValidity::Valid =
-        check_note_validity::<D>(&note, &ephemeral_key, &output.cmstar_bytes())
-    {
-        Some((note, to, memo))
-    } else {
-        None
+    #[test]
+    fn decryption_with_domains() {
+        let cmx = MockCmx::for_note(0, 5);
+        let full = full_output(1, 0b01, 5, cmx);
+        let compact = compact_output(1, 0b01, 5, cmx);
+        let note = MockNote { ivk: 0, value: 5 };
+
+        // Only the last context can parse the note, so its index is returned.
+        let domains = [
+            ContextDomain { max_value: 3 },
+            ContextDomain { max_value: 4 },
+            ContextDomain { max_value: 5 },
+        ];
+        assert_eq!(
+            try_note_decryption_with_domains(&domains, &0, &full),
+            Some((2, (note, 0, ())))
+        );
+        assert_eq!(
+            try_compact_note_decryption_with_domains(&domains, &0, &compact),
+            Some((2, (note, 0)))
+        );
+
+        // The first context that can parse the note is reported.
+        let domains = [
+            ContextDomain { max_value: 4 },
+            ContextDomain { max_value: 6 },
+            ContextDomain { max_value: 5 },
+        ];
+        assert_eq!(
+            try_note_decryption_with_domains(&domains, &0, &full),
+            Some((1, (note, 0, ())))
+        );
+        assert_eq!(
+            try_compact_note_decryption_with_domains(&domains, &0, &compact),
+            Some((1, (note, 0)))
+        );
+
+        // No context can parse the note.
+        let domains = [
+            ContextDomain { max_value: 3 },
+            ContextDomain { max_value: 4 },
+        ];
+        assert_eq!(try_note_decryption_with_domains(&domains, &0, &full), None);
+        assert_eq!(
+            try_compact_note_decryption_with_domains(&domains, &0, &compact),
+            None
+        );
+
+        // No domain context can decrypt the output for an IVK it is not sent to.
+        let domains = [ContextDomain { max_value: 5 }];
+        assert_eq!(try_note_decryption_with_domains(&domains, &1, &full), None);
+        assert_eq!(
+            try_compact_note_decryption_with_domains(&domains, &1, &compact),
+            None
+        );
+        assert_eq!(
+            try_note_decryption_with_domains::<ContextDomain, _>(&[], &0, &full),
+            None
+        );
     }
 }
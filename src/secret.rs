@@ -0,0 +1,92 @@
+//! Integration with the [`secrecy`] crate.
+//!
+//! These wrappers return key material inside [`Secret`], which does not implement
+//! `Clone` or `Debug` for the wrapped types and zeroizes them on drop. Callers must use
+//! [`ExposeSecret::expose_secret`] to access the key explicitly.
+
+use secrecy::{ExposeSecret, Secret};
+use zeroize::Zeroize;
+
+use crate::{
+    consts::{ENC_CIPHERTEXT_SIZE, OUT_CIPHERTEXT_SIZE},
+    Domain, EphemeralKeyBytes, OutgoingCipherKey, ShieldedOutput,
+};
+
+/// Derives the [`OutgoingCipherKey`] for an encrypted note, wrapped in a [`Secret`].
+///
+/// See [`Domain::derive_ock`].
+pub fn derive_ock<D: Domain>(
+    ovk: &D::OutgoingViewingKey,
+    cv: &D::ValueCommitment,
+    cmstar_bytes: &D::ExtractedCommitmentBytes,
+    ephemeral_key: &EphemeralKeyBytes,
+) -> Secret<OutgoingCipherKey> {
+    Secret::new(D::derive_ock(ovk, cv, cmstar_bytes, ephemeral_key))
+}
+
+/// Derives the symmetric key from the given shared secret, wrapped in a [`Secret`].
+///
+/// This requires the domain's `SymmetricKey` to implement [`Zeroize`]. See
+/// [`Domain::kdf`].
+pub fn kdf<D: Domain>(
+    secret: D::SharedSecret,
+    ephemeral_key: &EphemeralKeyBytes,
+) -> Secret<D::SymmetricKey>
+where
+    D::SymmetricKey: Zeroize,
+{
+    Secret::new(D::kdf(secret, ephemeral_key))
+}
+
+/// Recovery of the full note plaintext by the sender, using an [`OutgoingCipherKey`]
+/// held in a [`Secret`].
+///
+/// See [`crate::try_output_recovery_with_ock`].
+#[allow(clippy::type_complexity)]
+pub fn try_output_recovery_with_ock<D: Domain, Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>>(
+    domain: &D,
+    ock: &Secret<OutgoingCipherKey>,
+    output: &Output,
+    out_ciphertext: &[u8; OUT_CIPHERTEXT_SIZE],
+) -> Option<(D::Note, D::Recipient, D::Memo)> {
+    crate::try_output_recovery_with_ock(domain, ock.expose_secret(), output, out_ciphertext)
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use secrecy::ExposeSecret;
+
+    use super::{derive_ock, kdf, try_output_recovery_with_ock};
+    use crate::test_domain::{sent_output, MockCmx, MockDomain, MockNote};
+    use crate::{Domain, EphemeralKeyBytes};
+
+    #[test]
+    fn recovery_with_secret_ock() {
+        let sent = sent_output(1, 0b01, 1, MockCmx::for_note(0, 1));
+        let ock = derive_ock::<MockDomain>(&(), &(), &sent.output.cmx, &sent.output.ephemeral_key);
+        assert_eq!(
+            ock.expose_secret().as_ref(),
+            MockDomain::derive_ock(&(), &(), &sent.output.cmx, &sent.output.ephemeral_key).as_ref()
+        );
+        assert_eq!(
+            try_output_recovery_with_ock(&MockDomain, &ock, &sent, &sent.out_ciphertext),
+            Some((MockNote { ivk: 0, value: 1 }, 0, ()))
+        );
+
+        let mut out_ciphertext = sent.out_ciphertext;
+        out_ciphertext[0] ^= 1;
+        assert_eq!(
+            try_output_recovery_with_ock(&MockDomain, &ock, &sent, &out_ciphertext),
+            None
+        );
+    }
+
+    #[test]
+    fn kdf_matches_domain() {
+        let ephemeral_key = EphemeralKeyBytes([1; 32]);
+        assert_eq!(
+            kdf::<MockDomain>((), &ephemeral_key).expose_secret(),
+            &MockDomain::kdf((), &ephemeral_key)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! The individual stages of trial decryption.
+//!
+//! [`crate::try_note_decryption`] and [`crate::try_compact_note_decryption`] are
+//! composed of the following stages, which are exposed here so that they can be measured
+//! in isolation (for example, by downstream benchmark suites):
+//!
+//! 1. [`prepare_epk`]: parse and prepare the output's ephemeral public key.
+//! 2. [`ka_agree_dec`]: derive the shared secret from the `ivk` and prepared key.
+//! 3. [`kdf`]: derive the symmetric key from the shared secret.
+//! 4. [`decrypt_note_ciphertext`] or [`decrypt_compact_note_ciphertext`]: decrypt the
+//!    note ciphertext.
+//! 5. [`parse_note_plaintext`]: parse the note plaintext and check the note's validity.
+//!
+//! Most callers should use the composed functions instead.
+
+use crate::consts::{COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE};
+use crate::{Domain, EphemeralKeyBytes, NotePlaintextBytes};
+
+/// Parses and prepares the given ephemeral key.
+///
+/// Returns `None` if `ephemeral_key` is not a valid encoding of an
+/// `EphemeralPublicKey`.
+pub fn prepare_epk<D: Domain>(
+    ephemeral_key: &EphemeralKeyBytes,
+) -> Option<D::PreparedEphemeralPublicKey> {
+    D::epk(ephemeral_key).map(D::prepare_epk)
+}
+
+/// Derives the shared secret for trial decryption with the given `ivk`.
+pub fn ka_agree_dec<D: Domain>(
+    ivk: &D::IncomingViewingKey,
+    epk: &D::PreparedEphemeralPublicKey,
+) -> D::SharedSecret {
+    D::ka_agree_dec(ivk, epk)
+}
+
+/// Derives the symmetric key from the given shared secret.
+pub fn kdf<D: Domain>(
+    secret: D::SharedSecret,
+    ephemeral_key: &EphemeralKeyBytes,
+) -> D::SymmetricKey {
+    D::kdf(secret, ephemeral_key)
+}
+
+/// Decrypts and authenticates the given note ciphertext.
+///
+/// Returns `None` if authentication fails.
+pub fn decrypt_note_ciphertext<D: Domain>(
+    key: &D::SymmetricKey,
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+) -> Option<NotePlaintextBytes> {
+    crate::decrypt_note_ciphertext::<D>(key, enc_ciphertext)
+}
+
+/// Decrypts the given compact note ciphertext.
+///
+/// Compact ciphertexts are not authenticated, so this only returns `None` if `key` is
+/// malformed.
+pub fn decrypt_compact_note_ciphertext<D: Domain>(
+    key: &D::SymmetricKey,
+    enc_ciphertext: &[u8; COMPACT_NOTE_SIZE],
+) -> Option<[u8; COMPACT_NOTE_SIZE]> {
+    crate::decrypt_compact_note_ciphertext::<D>(key, enc_ciphertext)
+}
+
+/// Parses the given note plaintext, and checks that the resulting note is consistent
+/// with the output's `ephemeral_key` and `cmstar_bytes`.
+///
+/// `plaintext` may be either a full or a compact note plaintext.
+pub fn parse_note_plaintext<D: Domain>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    ephemeral_key: &EphemeralKeyBytes,
+    cmstar_bytes: &D::ExtractedCommitmentBytes,
+    plaintext: &[u8],
+) -> Option<(D::Note, D::Recipient)> {
+    crate::parse_note_plaintext_without_memo_ivk(
+        domain,
+        ivk,
+        ephemeral_key,
+        cmstar_bytes,
+        plaintext,
+    )
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::{
+        decrypt_compact_note_ciphertext, decrypt_note_ciphertext, ka_agree_dec, kdf,
+        parse_note_plaintext, prepare_epk,
+    };
+    use crate::test_domain::{
+        compact_output, full_output, MockCmx, MockDomain, INVALID_EPK, ZIP_212_VALUE,
+    };
+    use crate::{try_compact_note_decryption, try_note_decryption};
+
+    #[test]
+    fn composed_stages_match_trial_decryption() {
+        let cases = [
+            // Decrypts to a valid note.
+            (1, 0b01, 1, MockCmx::for_note(0, 1)),
+            // Decrypts to a valid post-ZIP 212 note.
+            (
+                ZIP_212_VALUE,
+                0b01,
+                ZIP_212_VALUE,
+                MockCmx::for_note(0, ZIP_212_VALUE),
+            ),
+            // The plaintext cannot be parsed.
+            (1, 0b10, 1, MockCmx::for_note(1, 1)),
+            // The note does not match the commitment.
+            (1, 0b01, 1, MockCmx::for_note(0, 2)),
+            // The note does not match the ephemeral key.
+            (1, 0b01, ZIP_212_VALUE, MockCmx::for_note(0, ZIP_212_VALUE)),
+            // The ephemeral key is invalid.
+            (INVALID_EPK, 0b01, 1, MockCmx::for_note(0, 1)),
+        ];
+
+        for (ek, mask, value, cmx) in cases {
+            let full = full_output(ek, mask, value, cmx);
+            let compact = compact_output(ek, mask, value, cmx);
+            let ephemeral_key = full.ephemeral_key;
+
+            let key = prepare_epk::<MockDomain>(&ephemeral_key).map(|epk| {
+                #[allow(clippy::let_unit_value)]
+                let shared_secret = ka_agree_dec::<MockDomain>(&0, &epk);
+                kdf::<MockDomain>(shared_secret, &ephemeral_key)
+            });
+
+            let composed = key
+                .and_then(|key| decrypt_note_ciphertext::<MockDomain>(&key, &full.enc_ciphertext))
+                .and_then(|plaintext| {
+                    parse_note_plaintext(&MockDomain, &0, &ephemeral_key, &cmx, &plaintext.0)
+                });
+            assert_eq!(
+                composed,
+                try_note_decryption(&MockDomain, &0, &full).map(|(note, to, _)| (note, to))
+            );
+
+            let composed = key
+                .and_then(|key| {
+                    decrypt_compact_note_ciphertext::<MockDomain>(&key, &compact.enc_ciphertext)
+                })
+                .and_then(|plaintext| {
+                    parse_note_plaintext(&MockDomain, &0, &ephemeral_key, &cmx, &plaintext)
+                });
+            assert_eq!(
+                composed,
+                try_compact_note_decryption(&MockDomain, &0, &compact)
+            );
+        }
+    }
+}
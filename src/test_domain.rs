@@ -0,0 +1,525 @@
+//! A minimal `Domain` for unit tests.
+//!
+//! The symmetric key for an output is its `ephemeral_key`, so every IVK derives the same
+//! key and decrypts the same outputs. Which IVKs can parse a note plaintext is controlled
+//! by a bitmask in its first byte: IVK `i` parses it if bit `i` is set, producing a note
+//! whose commitment depends on `i`. This makes it possible to construct outputs that
+//! decrypt to an invalid note under one IVK and to a valid note under another.
+//!
+//! For recovery by the sender, `pk_d` is the lowest IVK in the mask. Notes with a value
+//! of at least [`ZIP_212_VALUE`] are treated as post-ZIP 212 notes whose `esk` is their
+//! value, so their outputs must have ephemeral key `[value; 32]`.
+//!
+//! `MockDomain::batch_validate_notes` additionally rejects notes decrypted from outputs
+//! with ephemeral key `[BATCH_REJECTED_EK; 32]`, which the non-batched validity check
+//! accepts, and returns no results at all for a batch containing an output with
+//! ephemeral key `[BATCH_FAULTY_EK; 32]`.
+
+use alloc::vec::Vec;
+
+#[cfg(any(feature = "std", feature = "test-dependencies"))]
+use rand_core::{impls, Error, RngCore};
+
+use crate::consts::{
+    COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE, NOTE_PLAINTEXT_SIZE, OUT_CIPHERTEXT_SIZE,
+    OUT_PLAINTEXT_SIZE,
+};
+use crate::{
+    check_note_validity, encrypt_note_plaintext_with_key, encrypt_outgoing_plaintext_with_ock,
+    Domain, EphemeralKeyBytes, NotePlaintextBytes, NoteValidity, OutPlaintextBytes,
+    OutgoingCipherKey, RecoverableOutput, ShieldedOutput,
+};
+
+/// The first byte of an ephemeral key that [`MockDomain::epk`] rejects.
+pub(crate) const INVALID_EPK: u8 = 0xff;
+
+/// The ephemeral key byte for which [`MockDomain`]'s `batch_validate_notes` rejects
+/// otherwise-valid notes.
+pub(crate) const BATCH_REJECTED_EK: u8 = 0x7e;
+
+/// The ephemeral key byte for which [`MockDomain`]'s `batch_validate_notes` returns the
+/// wrong number of results.
+pub(crate) const BATCH_FAULTY_EK: u8 = 0x7d;
+
+/// The smallest note value for which [`MockDomain::derive_esk`] returns an `esk`.
+pub(crate) const ZIP_212_VALUE: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct MockDomain;
+
+/// A note as parsed by IVK `ivk`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct MockNote {
+    pub(crate) ivk: u8,
+    pub(crate) value: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct MockCmx(pub(crate) [u8; 2]);
+
+impl AsRef<[u8]> for MockCmx {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&MockCmx> for MockCmx {
+    fn from(cmx: &MockCmx) -> Self {
+        *cmx
+    }
+}
+
+/// Parses a 32-byte encoding whose trailing 30 bytes are zero.
+#[cfg(feature = "std")]
+impl TryFrom<&[u8]> for MockCmx {
+    type Error = ();
+
+    fn try_from(bytes: &[u8]) -> Result<Self, ()> {
+        match bytes {
+            [a, b, rest @ ..] if rest.len() == 30 && rest.iter().all(|&x| x == 0) => {
+                Ok(MockCmx([*a, *b]))
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+impl MockCmx {
+    /// Returns the commitment of the note that IVK `ivk` parses with the given value.
+    pub(crate) fn for_note(ivk: u8, value: u8) -> Self {
+        MockCmx([ivk, value])
+    }
+}
+
+impl Domain for MockDomain {
+    type EphemeralSecretKey = u8;
+    type EphemeralPublicKey = EphemeralKeyBytes;
+    type PreparedEphemeralPublicKey = EphemeralKeyBytes;
+    type SharedSecret = ();
+    type SymmetricKey = [u8; 32];
+    type Note = MockNote;
+    type Recipient = u8;
+    type DiversifiedTransmissionKey = u8;
+    type IncomingViewingKey = u8;
+    type OutgoingViewingKey = ();
+    type ValueCommitment = ();
+    type ExtractedCommitment = MockCmx;
+    type ExtractedCommitmentBytes = MockCmx;
+    type Memo = ();
+
+    fn derive_esk(note: &Self::Note) -> Option<Self::EphemeralSecretKey> {
+        if note.value >= ZIP_212_VALUE {
+            Some(note.value)
+        } else {
+            None
+        }
+    }
+
+    fn get_pk_d(note: &Self::Note) -> Self::DiversifiedTransmissionKey {
+        // When encrypting, this is the lowest IVK in the mask (or 8 if the mask is empty).
+        note.ivk.trailing_zeros() as u8
+    }
+
+    fn prepare_epk(epk: Self::EphemeralPublicKey) -> Self::PreparedEphemeralPublicKey {
+        epk
+    }
+
+    fn ka_derive_public(
+        _note: &Self::Note,
+        esk: &Self::EphemeralSecretKey,
+    ) -> Self::EphemeralPublicKey {
+        EphemeralKeyBytes([*esk; 32])
+    }
+
+    fn ka_agree_enc(
+        _esk: &Self::EphemeralSecretKey,
+        _pk_d: &Self::DiversifiedTransmissionKey,
+    ) -> Self::SharedSecret {
+    }
+
+    fn ka_agree_dec(
+        _ivk: &Self::IncomingViewingKey,
+        _epk: &Self::PreparedEphemeralPublicKey,
+    ) -> Self::SharedSecret {
+    }
+
+    fn kdf(_secret: Self::SharedSecret, ephemeral_key: &EphemeralKeyBytes) -> Self::SymmetricKey {
+        ephemeral_key.0
+    }
+
+    fn note_plaintext_bytes(note: &Self::Note, _memo: &Self::Memo) -> NotePlaintextBytes {
+        // When encrypting, `note.ivk` is the mask of IVKs that can parse the plaintext.
+        let mut plaintext = [0; NOTE_PLAINTEXT_SIZE];
+        plaintext[0] = note.ivk;
+        plaintext[1] = note.value;
+        NotePlaintextBytes(plaintext)
+    }
+
+    fn derive_ock(
+        _ovk: &Self::OutgoingViewingKey,
+        _cv: &Self::ValueCommitment,
+        _cmstar_bytes: &Self::ExtractedCommitmentBytes,
+        _ephemeral_key: &EphemeralKeyBytes,
+    ) -> OutgoingCipherKey {
+        OutgoingCipherKey([0; 32])
+    }
+
+    fn outgoing_plaintext_bytes(
+        note: &Self::Note,
+        esk: &Self::EphemeralSecretKey,
+    ) -> OutPlaintextBytes {
+        let mut out_plaintext = [0; OUT_PLAINTEXT_SIZE];
+        out_plaintext[0] = Self::get_pk_d(note);
+        out_plaintext[1] = *esk;
+        OutPlaintextBytes(out_plaintext)
+    }
+
+    fn epk_bytes(epk: &Self::EphemeralPublicKey) -> EphemeralKeyBytes {
+        *epk
+    }
+
+    fn epk(ephemeral_key: &EphemeralKeyBytes) -> Option<Self::EphemeralPublicKey> {
+        if ephemeral_key.0[0] == INVALID_EPK {
+            None
+        } else {
+            Some(*ephemeral_key)
+        }
+    }
+
+    fn cmstar(note: &Self::Note) -> Self::ExtractedCommitment {
+        MockCmx::for_note(note.ivk, note.value)
+    }
+
+    fn parse_note_plaintext_without_memo_ivk(
+        &self,
+        ivk: &Self::IncomingViewingKey,
+        plaintext: &[u8],
+    ) -> Option<(Self::Note, Self::Recipient)> {
+        if *ivk < 8 && plaintext[0] & (1 << ivk) != 0 {
+            Some((
+                MockNote {
+                    ivk: *ivk,
+                    value: plaintext[1],
+                },
+                *ivk,
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn parse_note_plaintext_without_memo_ovk(
+        &self,
+        pk_d: &Self::DiversifiedTransmissionKey,
+        plaintext: &NotePlaintextBytes,
+    ) -> Option<(Self::Note, Self::Recipient)> {
+        self.parse_note_plaintext_without_memo_ivk(pk_d, &plaintext.0)
+    }
+
+    fn extract_memo(&self, _plaintext: &NotePlaintextBytes) -> Self::Memo {}
+
+    fn extract_pk_d(out_plaintext: &OutPlaintextBytes) -> Option<Self::DiversifiedTransmissionKey> {
+        Some(out_plaintext.0[0]).filter(|pk_d| *pk_d < 8)
+    }
+
+    fn extract_esk(out_plaintext: &OutPlaintextBytes) -> Option<Self::EphemeralSecretKey> {
+        Some(out_plaintext.0[1])
+    }
+
+    fn pk_d_for_recipient(recipient: &Self::Recipient) -> Option<Self::DiversifiedTransmissionKey> {
+        Some(*recipient)
+    }
+}
+
+impl crate::BatchDomain for MockDomain {
+    fn batch_validate_notes<'a>(
+        items: impl Iterator<Item = (&'a MockNote, &'a EphemeralKeyBytes, &'a MockCmx)>,
+    ) -> Vec<bool> {
+        let items = items.collect::<Vec<_>>();
+        if items
+            .iter()
+            .any(|(_, ephemeral_key, _)| ephemeral_key.0[0] == BATCH_FAULTY_EK)
+        {
+            return Vec::new();
+        }
+
+        items
+            .into_iter()
+            .map(|(note, ephemeral_key, cmx)| {
+                ephemeral_key.0[0] != BATCH_REJECTED_EK
+                    && matches!(
+                        check_note_validity::<MockDomain>(note, ephemeral_key, cmx),
+                        NoteValidity::Valid
+                    )
+            })
+            .collect()
+    }
+}
+
+/// A [`MockDomain`] with a runtime context: notes with a value above `max_value` cannot
+/// be parsed under it, as if they were created under a later consensus rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ContextDomain {
+    pub(crate) max_value: u8,
+}
+
+impl Domain for ContextDomain {
+    type EphemeralSecretKey = u8;
+    type EphemeralPublicKey = EphemeralKeyBytes;
+    type PreparedEphemeralPublicKey = EphemeralKeyBytes;
+    type SharedSecret = ();
+    type SymmetricKey = [u8; 32];
+    type Note = MockNote;
+    type Recipient = u8;
+    type DiversifiedTransmissionKey = u8;
+    type IncomingViewingKey = u8;
+    type OutgoingViewingKey = ();
+    type ValueCommitment = ();
+    type ExtractedCommitment = MockCmx;
+    type ExtractedCommitmentBytes = MockCmx;
+    type Memo = ();
+
+    fn derive_esk(note: &Self::Note) -> Option<Self::EphemeralSecretKey> {
+        MockDomain::derive_esk(note)
+    }
+
+    fn get_pk_d(note: &Self::Note) -> Self::DiversifiedTransmissionKey {
+        MockDomain::get_pk_d(note)
+    }
+
+    fn prepare_epk(epk: Self::EphemeralPublicKey) -> Self::PreparedEphemeralPublicKey {
+        MockDomain::prepare_epk(epk)
+    }
+
+    fn ka_derive_public(
+        note: &Self::Note,
+        esk: &Self::EphemeralSecretKey,
+    ) -> Self::EphemeralPublicKey {
+        MockDomain::ka_derive_public(note, esk)
+    }
+
+    fn ka_agree_enc(
+        esk: &Self::EphemeralSecretKey,
+        pk_d: &Self::DiversifiedTransmissionKey,
+    ) -> Self::SharedSecret {
+        MockDomain::ka_agree_enc(esk, pk_d)
+    }
+
+    fn ka_agree_dec(
+        ivk: &Self::IncomingViewingKey,
+        epk: &Self::PreparedEphemeralPublicKey,
+    ) -> Self::SharedSecret {
+        MockDomain::ka_agree_dec(ivk, epk)
+    }
+
+    fn kdf(secret: Self::SharedSecret, ephemeral_key: &EphemeralKeyBytes) -> Self::SymmetricKey {
+        MockDomain::kdf(secret, ephemeral_key)
+    }
+
+    fn note_plaintext_bytes(note: &Self::Note, memo: &Self::Memo) -> NotePlaintextBytes {
+        MockDomain::note_plaintext_bytes(note, memo)
+    }
+
+    fn derive_ock(
+        ovk: &Self::OutgoingViewingKey,
+        cv: &Self::ValueCommitment,
+        cmstar_bytes: &Self::ExtractedCommitmentBytes,
+        ephemeral_key: &EphemeralKeyBytes,
+    ) -> OutgoingCipherKey {
+        MockDomain::derive_ock(ovk, cv, cmstar_bytes, ephemeral_key)
+    }
+
+    fn outgoing_plaintext_bytes(
+        note: &Self::Note,
+        esk: &Self::EphemeralSecretKey,
+    ) -> OutPlaintextBytes {
+        MockDomain::outgoing_plaintext_bytes(note, esk)
+    }
+
+    fn epk_bytes(epk: &Self::EphemeralPublicKey) -> EphemeralKeyBytes {
+        MockDomain::epk_bytes(epk)
+    }
+
+    fn epk(ephemeral_key: &EphemeralKeyBytes) -> Option<Self::EphemeralPublicKey> {
+        MockDomain::epk(ephemeral_key)
+    }
+
+    fn cmstar(note: &Self::Note) -> Self::ExtractedCommitment {
+        MockDomain::cmstar(note)
+    }
+
+    fn parse_note_plaintext_without_memo_ivk(
+        &self,
+        ivk: &Self::IncomingViewingKey,
+        plaintext: &[u8],
+    ) -> Option<(Self::Note, Self::Recipient)> {
+        MockDomain
+            .parse_note_plaintext_without_memo_ivk(ivk, plaintext)
+            .filter(|(note, _)| note.value <= self.max_value)
+    }
+
+    fn parse_note_plaintext_without_memo_ovk(
+        &self,
+        pk_d: &Self::DiversifiedTransmissionKey,
+        plaintext: &NotePlaintextBytes,
+    ) -> Option<(Self::Note, Self::Recipient)> {
+        self.parse_note_plaintext_without_memo_ivk(pk_d, &plaintext.0)
+    }
+
+    fn extract_memo(&self, _plaintext: &NotePlaintextBytes) -> Self::Memo {}
+
+    fn extract_pk_d(out_plaintext: &OutPlaintextBytes) -> Option<Self::DiversifiedTransmissionKey> {
+        MockDomain::extract_pk_d(out_plaintext)
+    }
+
+    fn extract_esk(out_plaintext: &OutPlaintextBytes) -> Option<Self::EphemeralSecretKey> {
+        MockDomain::extract_esk(out_plaintext)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MockOutput<const CS: usize> {
+    pub(crate) ephemeral_key: EphemeralKeyBytes,
+    pub(crate) cmx: MockCmx,
+    pub(crate) enc_ciphertext: [u8; CS],
+}
+
+impl<const CS: usize> ShieldedOutput<MockDomain, CS> for MockOutput<CS> {
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        self.ephemeral_key
+    }
+
+    fn cmstar_bytes(&self) -> MockCmx {
+        self.cmx
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; CS] {
+        &self.enc_ciphertext
+    }
+}
+
+impl<const CS: usize> ShieldedOutput<ContextDomain, CS> for MockOutput<CS> {
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        self.ephemeral_key
+    }
+
+    fn cmstar_bytes(&self) -> MockCmx {
+        self.cmx
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; CS] {
+        &self.enc_ciphertext
+    }
+}
+
+/// Returns a full output with ephemeral key `[ek; 32]`, whose note plaintext can be
+/// parsed by the IVKs in `ivk_mask` and has commitment `cmx`.
+pub(crate) fn full_output(
+    ek: u8,
+    ivk_mask: u8,
+    value: u8,
+    cmx: MockCmx,
+) -> MockOutput<ENC_CIPHERTEXT_SIZE> {
+    let ephemeral_key = EphemeralKeyBytes([ek; 32]);
+    let note = MockNote {
+        ivk: ivk_mask,
+        value,
+    };
+    MockOutput {
+        ephemeral_key,
+        cmx,
+        enc_ciphertext: encrypt_note_plaintext_with_key::<MockDomain>(
+            &MockDomain::kdf((), &ephemeral_key),
+            &note,
+            &(),
+        ),
+    }
+}
+
+/// Returns the compact form of [`full_output`].
+pub(crate) fn compact_output(
+    ek: u8,
+    ivk_mask: u8,
+    value: u8,
+    cmx: MockCmx,
+) -> MockOutput<COMPACT_NOTE_SIZE> {
+    let output = full_output(ek, ivk_mask, value, cmx);
+    let mut enc_ciphertext = [0; COMPACT_NOTE_SIZE];
+    enc_ciphertext.copy_from_slice(&output.enc_ciphertext[..COMPACT_NOTE_SIZE]);
+    MockOutput {
+        ephemeral_key: output.ephemeral_key,
+        cmx: output.cmx,
+        enc_ciphertext,
+    }
+}
+
+/// A full output along with the fields needed to recover it with an OVK.
+#[derive(Clone, Debug)]
+pub(crate) struct MockSentOutput {
+    pub(crate) output: MockOutput<ENC_CIPHERTEXT_SIZE>,
+    pub(crate) out_ciphertext: [u8; OUT_CIPHERTEXT_SIZE],
+}
+
+impl ShieldedOutput<MockDomain, ENC_CIPHERTEXT_SIZE> for MockSentOutput {
+    fn ephemeral_key(&self) -> EphemeralKeyBytes {
+        self.output.ephemeral_key
+    }
+
+    fn cmstar_bytes(&self) -> MockCmx {
+        self.output.cmx
+    }
+
+    fn enc_ciphertext(&self) -> &[u8; ENC_CIPHERTEXT_SIZE] {
+        &self.output.enc_ciphertext
+    }
+}
+
+impl RecoverableOutput<MockDomain> for MockSentOutput {
+    fn cv(&self) -> &() {
+        &()
+    }
+
+    fn out_ciphertext(&self) -> &[u8; OUT_CIPHERTEXT_SIZE] {
+        &self.out_ciphertext
+    }
+}
+
+/// Returns [`full_output`] along with an `out_ciphertext` that any OVK can decrypt.
+pub(crate) fn sent_output(ek: u8, ivk_mask: u8, value: u8, cmx: MockCmx) -> MockSentOutput {
+    let note = MockNote {
+        ivk: ivk_mask,
+        value,
+    };
+    MockSentOutput {
+        output: full_output(ek, ivk_mask, value, cmx),
+        out_ciphertext: encrypt_outgoing_plaintext_with_ock(
+            &MockDomain::derive_ock(&(), &(), &cmx, &EphemeralKeyBytes([ek; 32])),
+            &MockDomain::outgoing_plaintext_bytes(&note, &ek),
+        ),
+    }
+}
+
+/// A deterministic counter-based RNG.
+#[cfg(any(feature = "std", feature = "test-dependencies"))]
+pub(crate) struct MockRng(pub(crate) u64);
+
+#[cfg(any(feature = "std", feature = "test-dependencies"))]
+impl RngCore for MockRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(1);
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
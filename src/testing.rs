@@ -0,0 +1,692 @@
+//! Utilities for testing code that uses this crate, and for testing this crate itself.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "alloc")]
+use rand_core::RngCore;
+
+use crate::consts::{COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE, OUT_CIPHERTEXT_SIZE};
+#[cfg(feature = "alloc")]
+use crate::NotePlaintextBytes;
+use crate::{
+    encrypt_note_plaintext_with_shared_secret, encrypt_outgoing_plaintext_with_ock,
+    try_compact_note_decryption, try_note_decryption, Domain, EphemeralKeyBytes, ShieldedOutput,
+};
+
+/// A reference implementation of trial decryption, against which this crate can be
+/// compared.
+///
+/// This would typically be implemented over an independent implementation of the
+/// protocol (for example, an earlier version of this crate, or a model of the
+/// specification), so that changes to the shared logic in this crate can be checked for
+/// divergences.
+pub trait ReferenceDecryptor<D: Domain> {
+    /// Trial decryption of the full note plaintext by the recipient.
+    ///
+    /// This must have the same semantics as [`crate::try_note_decryption`].
+    fn try_note_decryption(
+        &self,
+        domain: &D,
+        ivk: &D::IncomingViewingKey,
+        ephemeral_key: &EphemeralKeyBytes,
+        cmstar_bytes: &D::ExtractedCommitmentBytes,
+        enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+    ) -> Option<(D::Note, D::Recipient, D::Memo)>;
+
+    /// Trial decryption of the compact note plaintext by the recipient.
+    ///
+    /// This must have the same semantics as [`crate::try_compact_note_decryption`].
+    fn try_compact_note_decryption(
+        &self,
+        domain: &D,
+        ivk: &D::IncomingViewingKey,
+        ephemeral_key: &EphemeralKeyBytes,
+        cmstar_bytes: &D::ExtractedCommitmentBytes,
+        enc_ciphertext: &[u8; COMPACT_NOTE_SIZE],
+    ) -> Option<(D::Note, D::Recipient)>;
+}
+
+/// A difference between the results of this crate and a [`ReferenceDecryptor`].
+#[derive(Debug)]
+pub struct Divergence<T> {
+    /// The result produced by this crate.
+    pub ours: Option<T>,
+    /// The result produced by the reference implementation.
+    pub reference: Option<T>,
+}
+
+fn compare<T: PartialEq>(ours: Option<T>, reference: Option<T>) -> Result<(), Divergence<T>> {
+    if ours == reference {
+        Ok(())
+    } else {
+        Err(Divergence { ours, reference })
+    }
+}
+
+/// Runs [`crate::try_note_decryption`] and the given reference implementation on the
+/// same inputs, returning the results if they differ.
+#[allow(clippy::type_complexity)]
+pub fn check_note_decryption<
+    D: Domain,
+    R: ReferenceDecryptor<D>,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    reference: &R,
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Result<(), Divergence<(D::Note, D::Recipient, D::Memo)>>
+where
+    (D::Note, D::Recipient, D::Memo): PartialEq,
+{
+    compare(
+        try_note_decryption(domain, ivk, output),
+        reference.try_note_decryption(
+            domain,
+            ivk,
+            &output.ephemeral_key(),
+            &output.cmstar_bytes(),
+            output.enc_ciphertext(),
+        ),
+    )
+}
+
+/// Runs [`crate::try_compact_note_decryption`] and the given reference implementation on
+/// the same inputs, returning the results if they differ.
+#[allow(clippy::type_complexity)]
+pub fn check_compact_note_decryption<
+    D: Domain,
+    R: ReferenceDecryptor<D>,
+    Output: ShieldedOutput<D, COMPACT_NOTE_SIZE>,
+>(
+    reference: &R,
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> Result<(), Divergence<(D::Note, D::Recipient)>>
+where
+    (D::Note, D::Recipient): PartialEq,
+{
+    compare(
+        try_compact_note_decryption(domain, ivk, output),
+        reference.try_compact_note_decryption(
+            domain,
+            ivk,
+            &output.ephemeral_key(),
+            &output.cmstar_bytes(),
+            output.enc_ciphertext(),
+        ),
+    )
+}
+
+/// Returns `true` if `D1` and `D2` derive different symmetric keys from the given
+/// shared secrets and ephemeral key.
+///
+/// `secret1` and `secret2` should be the "same" shared secret in each domain (for
+/// example, decoded from the same bytes), so that the only difference between the two
+/// derivations is the domain's KDF personalization. A result of `false` indicates that
+/// the two domains are not domain-separated, which is usually caused by copying a KDF
+/// implementation without changing its personalization.
+pub fn kdf_is_domain_separated<D1: Domain, D2: Domain>(
+    secret1: D1::SharedSecret,
+    secret2: D2::SharedSecret,
+    ephemeral_key: &EphemeralKeyBytes,
+) -> bool {
+    D1::kdf(secret1, ephemeral_key).as_ref() != D2::kdf(secret2, ephemeral_key).as_ref()
+}
+
+/// The inputs to [`Domain::derive_ock`] other than the ephemeral key.
+pub struct OckInputs<'a, D: Domain> {
+    /// The outgoing viewing key.
+    pub ovk: &'a D::OutgoingViewingKey,
+    /// The value commitment.
+    pub cv: &'a D::ValueCommitment,
+    /// The extracted note commitment.
+    pub cmstar_bytes: &'a D::ExtractedCommitmentBytes,
+}
+
+/// Returns `true` if `D1` and `D2` derive different outgoing cipher keys from the given
+/// inputs and ephemeral key.
+///
+/// As with [`kdf_is_domain_separated`], `inputs1` and `inputs2` should be the "same"
+/// inputs in each domain, so that a result of `false` indicates a missing or duplicated
+/// personalization in one of the domains' [`Domain::derive_ock`] implementations.
+pub fn ock_is_domain_separated<D1: Domain, D2: Domain>(
+    inputs1: OckInputs<'_, D1>,
+    inputs2: OckInputs<'_, D2>,
+    ephemeral_key: &EphemeralKeyBytes,
+) -> bool {
+    let ock1 = D1::derive_ock(inputs1.ovk, inputs1.cv, inputs1.cmstar_bytes, ephemeral_key);
+    let ock2 = D2::derive_ock(inputs2.ovk, inputs2.cv, inputs2.cmstar_bytes, ephemeral_key);
+    ock1.0 != ock2.0
+}
+
+/// Deterministically encrypts a note, producing the `ephemeral_key`, `encCiphertext`,
+/// and `outCiphertext` fields of the corresponding output.
+///
+/// This reproduces the ciphertexts that [`crate::NoteEncryption`] would produce for the
+/// same inputs, without requiring the `pre-zip-212` feature to supply `esk`. It is
+/// intended for generating and checking known-answer test vectors. `esk` is used as-is;
+/// for notes created after [ZIP 212] activation it must be equal to
+/// [`Domain::derive_esk`] of the note.
+///
+/// [ZIP 212]: https://zips.z.cash/zip-0212
+#[allow(clippy::type_complexity)]
+pub fn encrypt_deterministic<D: Domain>(
+    esk: &D::EphemeralSecretKey,
+    ovk: &D::OutgoingViewingKey,
+    note: &D::Note,
+    memo: &D::Memo,
+    cv: &D::ValueCommitment,
+    cmstar: &D::ExtractedCommitment,
+) -> (
+    EphemeralKeyBytes,
+    [u8; ENC_CIPHERTEXT_SIZE],
+    [u8; OUT_CIPHERTEXT_SIZE],
+) {
+    let ephemeral_key = D::epk_bytes(&D::ka_derive_public(note, esk));
+
+    let shared_secret = D::ka_agree_enc(esk, &D::get_pk_d(note));
+    let enc_ciphertext =
+        encrypt_note_plaintext_with_shared_secret::<D>(shared_secret, &ephemeral_key, note, memo);
+
+    let ock = D::derive_ock(ovk, cv, &cmstar.into(), &ephemeral_key);
+    let out_ciphertext =
+        encrypt_outgoing_plaintext_with_ock(&ock, &D::outgoing_plaintext_bytes(note, esk));
+
+    (ephemeral_key, enc_ciphertext, out_ciphertext)
+}
+
+/// The domain-specific inputs from which a test vector is generated.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub struct TestVectorInputs<D: Domain> {
+    /// The incoming viewing key of the recipient.
+    pub ivk: D::IncomingViewingKey,
+    /// The outgoing viewing key of the sender.
+    pub ovk: D::OutgoingViewingKey,
+    /// The note being sent. This must have been created after [ZIP 212] activation.
+    ///
+    /// [ZIP 212]: https://zips.z.cash/zip-0212
+    pub note: D::Note,
+    /// The memo being sent.
+    pub memo: D::Memo,
+    /// The value commitment for the note.
+    pub cv: D::ValueCommitment,
+}
+
+/// Trait for domains that can generate inputs for test vectors.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub trait TestVectorDomain: Domain + Sized {
+    /// Samples a set of keys, and a note sent to them, using the given RNG.
+    fn sample_inputs<R: RngCore>(&self, rng: &mut R) -> TestVectorInputs<Self>;
+
+    /// Appends the encodings of `inputs.ivk`, `inputs.ovk`, and `inputs.cv` to `buf`.
+    ///
+    /// The note and memo are encoded by this crate via [`Domain::note_plaintext_bytes`].
+    fn write_inputs(inputs: &TestVectorInputs<Self>, buf: &mut Vec<u8>);
+
+    /// Appends the encoding of `cmstar_bytes` to `buf`.
+    fn write_cmstar_bytes(cmstar_bytes: &Self::ExtractedCommitmentBytes, buf: &mut Vec<u8>);
+}
+
+/// A test vector for note encryption and decryption in some domain.
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub struct TestVector<D: Domain> {
+    /// The inputs from which this test vector was generated.
+    pub inputs: TestVectorInputs<D>,
+    /// The `cmstar_bytes` field of the output.
+    pub cmstar_bytes: D::ExtractedCommitmentBytes,
+    /// The `ephemeral_key` field of the output.
+    pub ephemeral_key: EphemeralKeyBytes,
+    /// The note plaintext that decryption of the output with `inputs.ivk` must produce.
+    pub note_plaintext: NotePlaintextBytes,
+    /// The `encCiphertext` field of the output.
+    pub enc_ciphertext: [u8; ENC_CIPHERTEXT_SIZE],
+    /// The `outCiphertext` field of the output.
+    pub out_ciphertext: [u8; OUT_CIPHERTEXT_SIZE],
+}
+
+#[cfg(feature = "alloc")]
+impl<D: TestVectorDomain> TestVector<D> {
+    /// Appends the encoding of this test vector to `buf`.
+    ///
+    /// The encoding is the concatenation of:
+    /// - the domain's encoding of the inputs, via [`TestVectorDomain::write_inputs`];
+    /// - the domain's encoding of `cmstar_bytes`, via
+    ///   [`TestVectorDomain::write_cmstar_bytes`];
+    /// - `ephemeral_key` (32 bytes);
+    /// - `note_plaintext` ([`NOTE_PLAINTEXT_SIZE`](crate::NOTE_PLAINTEXT_SIZE) bytes);
+    /// - `enc_ciphertext` ([`ENC_CIPHERTEXT_SIZE`] bytes);
+    /// - `out_ciphertext` ([`OUT_CIPHERTEXT_SIZE`] bytes).
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        D::write_inputs(&self.inputs, buf);
+        D::write_cmstar_bytes(&self.cmstar_bytes, buf);
+        buf.extend_from_slice(&self.ephemeral_key.0);
+        buf.extend_from_slice(&self.note_plaintext.0);
+        buf.extend_from_slice(&self.enc_ciphertext);
+        buf.extend_from_slice(&self.out_ciphertext);
+    }
+}
+
+/// Generates `count` test vectors for the given domain.
+///
+/// The vectors are fully determined by the state of `rng`, so a seeded RNG can be used
+/// to regenerate the same vectors (for example, after a change to the note plaintext
+/// layout).
+///
+/// # Panics
+///
+/// Panics if [`TestVectorDomain::sample_inputs`] returns a note created prior to
+/// [ZIP 212] activation.
+///
+/// [ZIP 212]: https://zips.z.cash/zip-0212
+#[cfg(feature = "alloc")]
+#[cfg_attr(docsrs, doc(cfg(feature = "alloc")))]
+pub fn generate_test_vectors<D: TestVectorDomain, R: RngCore>(
+    domain: &D,
+    rng: &mut R,
+    count: usize,
+) -> Vec<TestVector<D>> {
+    (0..count)
+        .map(|_| {
+            let inputs = domain.sample_inputs(rng);
+            let esk = D::derive_esk(&inputs.note).expect("ZIP 212 is active.");
+            let cmstar = D::cmstar(&inputs.note);
+
+            let (ephemeral_key, enc_ciphertext, out_ciphertext) = encrypt_deterministic::<D>(
+                &esk,
+                &inputs.ovk,
+                &inputs.note,
+                &inputs.memo,
+                &inputs.cv,
+                &cmstar,
+            );
+
+            TestVector {
+                cmstar_bytes: (&cmstar).into(),
+                ephemeral_key,
+                note_plaintext: D::note_plaintext_bytes(&inputs.note, &inputs.memo),
+                enc_ciphertext,
+                out_ciphertext,
+                inputs,
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use alloc::{vec, vec::Vec};
+    use rand_core::RngCore;
+
+    use super::{
+        check_compact_note_decryption, check_note_decryption, encrypt_deterministic,
+        generate_test_vectors, Divergence, ReferenceDecryptor, TestVector, TestVectorDomain,
+        TestVectorInputs,
+    };
+    use crate::consts::{COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE};
+    use crate::test_domain::{
+        compact_output, full_output, MockCmx, MockDomain, MockNote, MockOutput, MockRng,
+        ZIP_212_VALUE,
+    };
+    use crate::{
+        stages, try_note_decryption, try_output_recovery_with_ovk, Domain, EphemeralKeyBytes,
+        NoteEncryption, NotePlaintextBytes, OutPlaintextBytes, OutgoingCipherKey, ShieldedOutput,
+    };
+
+    /// A reference decryptor for [`MockDomain`] built from the individual stages of
+    /// trial decryption, which can optionally skip the note validity checks.
+    struct MockReference {
+        check_validity: bool,
+    }
+
+    impl MockReference {
+        fn decrypt(
+            &self,
+            ivk: &u8,
+            ephemeral_key: &EphemeralKeyBytes,
+            cmstar_bytes: &MockCmx,
+            plaintext: &[u8],
+        ) -> Option<(MockNote, u8)> {
+            stages::prepare_epk::<MockDomain>(ephemeral_key)?;
+            let (note, to) = MockDomain.parse_note_plaintext_without_memo_ivk(ivk, plaintext)?;
+            let valid = MockDomain::cmstar(&note) == *cmstar_bytes
+                && MockDomain::derive_esk(&note).map_or(true, |esk| {
+                    MockDomain::ka_derive_public(&note, &esk) == *ephemeral_key
+                });
+            if valid || !self.check_validity {
+                Some((note, to))
+            } else {
+                None
+            }
+        }
+    }
+
+    impl ReferenceDecryptor<MockDomain> for MockReference {
+        fn try_note_decryption(
+            &self,
+            _domain: &MockDomain,
+            ivk: &u8,
+            ephemeral_key: &EphemeralKeyBytes,
+            cmstar_bytes: &MockCmx,
+            enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+        ) -> Option<(MockNote, u8, ())> {
+            let key = MockDomain::kdf((), ephemeral_key);
+            let plaintext = stages::decrypt_note_ciphertext::<MockDomain>(&key, enc_ciphertext)?;
+            self.decrypt(ivk, ephemeral_key, cmstar_bytes, &plaintext.0)
+                .map(|(note, to)| (note, to, ()))
+        }
+
+        fn try_compact_note_decryption(
+            &self,
+            _domain: &MockDomain,
+            ivk: &u8,
+            ephemeral_key: &EphemeralKeyBytes,
+            cmstar_bytes: &MockCmx,
+            enc_ciphertext: &[u8; COMPACT_NOTE_SIZE],
+        ) -> Option<(MockNote, u8)> {
+            let key = MockDomain::kdf((), ephemeral_key);
+            let plaintext =
+                stages::decrypt_compact_note_ciphertext::<MockDomain>(&key, enc_ciphertext)?;
+            self.decrypt(ivk, ephemeral_key, cmstar_bytes, &plaintext)
+        }
+    }
+
+    #[test]
+    fn reference_decryptor_divergence() {
+        let faithful = MockReference {
+            check_validity: true,
+        };
+        let divergent = MockReference {
+            check_validity: false,
+        };
+
+        let cases = [
+            // Decrypts to a valid note.
+            (1, 0b01, 1, MockCmx::for_note(0, 1)),
+            (
+                ZIP_212_VALUE,
+                0b01,
+                ZIP_212_VALUE,
+                MockCmx::for_note(0, ZIP_212_VALUE),
+            ),
+            // The plaintext cannot be parsed.
+            (1, 0b10, 1, MockCmx::for_note(1, 1)),
+        ];
+        for (ek, mask, value, cmx) in cases {
+            let full = full_output(ek, mask, value, cmx);
+            let compact = compact_output(ek, mask, value, cmx);
+            for reference in [&faithful, &divergent] {
+                assert!(check_note_decryption(reference, &MockDomain, &0, &full).is_ok());
+                assert!(
+                    check_compact_note_decryption(reference, &MockDomain, &0, &compact).is_ok()
+                );
+            }
+        }
+
+        // The note does not match the commitment, which only the faithful reference
+        // detects.
+        let cmx = MockCmx::for_note(0, 2);
+        let full = full_output(1, 0b01, 1, cmx);
+        let compact = compact_output(1, 0b01, 1, cmx);
+        let note = MockNote { ivk: 0, value: 1 };
+        assert!(check_note_decryption(&faithful, &MockDomain, &0, &full).is_ok());
+        assert!(check_compact_note_decryption(&faithful, &MockDomain, &0, &compact).is_ok());
+        assert!(matches!(
+            check_note_decryption(&divergent, &MockDomain, &0, &full),
+            Err(Divergence {
+                ours: None,
+                reference: Some(res),
+            }) if res == (note, 0, ())
+        ));
+        assert!(matches!(
+            check_compact_note_decryption(&divergent, &MockDomain, &0, &compact),
+            Err(Divergence {
+                ours: None,
+                reference: Some(res),
+            }) if res == (note, 0)
+        ));
+    }
+
+    #[test]
+    fn deterministic_encryption_matches_note_encryption() {
+        let note = MockNote {
+            ivk: 0b01,
+            value: ZIP_212_VALUE,
+        };
+        let cmstar = MockDomain::cmstar(&note);
+        let (ephemeral_key, enc_ciphertext, out_ciphertext) =
+            encrypt_deterministic::<MockDomain>(&ZIP_212_VALUE, &(), &note, &(), &(), &cmstar);
+
+        let ne = NoteEncryption::<MockDomain>::new(Some(()), note, ());
+        assert_eq!(ephemeral_key, MockDomain::epk_bytes(ne.epk()));
+        assert_eq!(enc_ciphertext[..], ne.encrypt_note_plaintext()[..]);
+        assert_eq!(
+            out_ciphertext[..],
+            ne.encrypt_outgoing_plaintext(&(), &cmstar, &mut MockRng(0))[..]
+        );
+
+        let output = MockOutput {
+            ephemeral_key,
+            cmx: MockCmx::for_note(0, ZIP_212_VALUE),
+            enc_ciphertext,
+        };
+        let expected = Some((
+            MockNote {
+                ivk: 0,
+                value: ZIP_212_VALUE,
+            },
+            0,
+            (),
+        ));
+        assert_eq!(try_note_decryption(&MockDomain, &0, &output), expected);
+        assert_eq!(
+            try_output_recovery_with_ovk(&MockDomain, &(), &output, &(), &out_ciphertext),
+            expected
+        );
+    }
+
+    /// A [`MockDomain`] whose notes record the index of their recipient's IVK, rather
+    /// than a mask of IVKs, so that encrypting a note and decrypting it produce the same
+    /// note.
+    struct IndexedDomain;
+
+    impl Domain for IndexedDomain {
+        type EphemeralSecretKey = u8;
+        type EphemeralPublicKey = EphemeralKeyBytes;
+        type PreparedEphemeralPublicKey = EphemeralKeyBytes;
+        type SharedSecret = ();
+        type SymmetricKey = [u8; 32];
+        type Note = MockNote;
+        type Recipient = u8;
+        type DiversifiedTransmissionKey = u8;
+        type IncomingViewingKey = u8;
+        type OutgoingViewingKey = ();
+        type ValueCommitment = ();
+        type ExtractedCommitment = MockCmx;
+        type ExtractedCommitmentBytes = MockCmx;
+        type Memo = ();
+
+        fn derive_esk(note: &MockNote) -> Option<u8> {
+            MockDomain::derive_esk(note)
+        }
+
+        fn get_pk_d(note: &MockNote) -> u8 {
+            note.ivk
+        }
+
+        fn prepare_epk(epk: EphemeralKeyBytes) -> EphemeralKeyBytes {
+            MockDomain::prepare_epk(epk)
+        }
+
+        fn ka_derive_public(note: &MockNote, esk: &u8) -> EphemeralKeyBytes {
+            MockDomain::ka_derive_public(note, esk)
+        }
+
+        fn ka_agree_enc(_esk: &u8, _pk_d: &u8) {}
+
+        fn ka_agree_dec(_ivk: &u8, _epk: &EphemeralKeyBytes) {}
+
+        fn kdf(secret: (), ephemeral_key: &EphemeralKeyBytes) -> [u8; 32] {
+            MockDomain::kdf(secret, ephemeral_key)
+        }
+
+        fn note_plaintext_bytes(note: &MockNote, memo: &()) -> NotePlaintextBytes {
+            MockDomain::note_plaintext_bytes(&to_mask(note), memo)
+        }
+
+        fn derive_ock(
+            ovk: &(),
+            cv: &(),
+            cmstar_bytes: &MockCmx,
+            ephemeral_key: &EphemeralKeyBytes,
+        ) -> OutgoingCipherKey {
+            MockDomain::derive_ock(ovk, cv, cmstar_bytes, ephemeral_key)
+        }
+
+        fn outgoing_plaintext_bytes(note: &MockNote, esk: &u8) -> OutPlaintextBytes {
+            MockDomain::outgoing_plaintext_bytes(&to_mask(note), esk)
+        }
+
+        fn epk_bytes(epk: &EphemeralKeyBytes) -> EphemeralKeyBytes {
+            MockDomain::epk_bytes(epk)
+        }
+
+        fn epk(ephemeral_key: &EphemeralKeyBytes) -> Option<EphemeralKeyBytes> {
+            MockDomain::epk(ephemeral_key)
+        }
+
+        fn cmstar(note: &MockNote) -> MockCmx {
+            MockDomain::cmstar(note)
+        }
+
+        fn parse_note_plaintext_without_memo_ivk(
+            &self,
+            ivk: &u8,
+            plaintext: &[u8],
+        ) -> Option<(MockNote, u8)> {
+            MockDomain.parse_note_plaintext_without_memo_ivk(ivk, plaintext)
+        }
+
+        fn parse_note_plaintext_without_memo_ovk(
+            &self,
+            pk_d: &u8,
+            plaintext: &NotePlaintextBytes,
+        ) -> Option<(MockNote, u8)> {
+            MockDomain.parse_note_plaintext_without_memo_ovk(pk_d, plaintext)
+        }
+
+        fn extract_memo(&self, _plaintext: &NotePlaintextBytes) {}
+
+        fn extract_pk_d(out_plaintext: &OutPlaintextBytes) -> Option<u8> {
+            MockDomain::extract_pk_d(out_plaintext)
+        }
+
+        fn extract_esk(out_plaintext: &OutPlaintextBytes) -> Option<u8> {
+            MockDomain::extract_esk(out_plaintext)
+        }
+    }
+
+    /// Returns the [`MockDomain`] form of an [`IndexedDomain`] note.
+    fn to_mask(note: &MockNote) -> MockNote {
+        MockNote {
+            ivk: 1 << note.ivk,
+            value: note.value,
+        }
+    }
+
+    impl TestVectorDomain for IndexedDomain {
+        fn sample_inputs<R: RngCore>(&self, rng: &mut R) -> TestVectorInputs<Self> {
+            let ivk = (rng.next_u32() % 8) as u8;
+            TestVectorInputs {
+                ivk,
+                ovk: (),
+                note: MockNote {
+                    ivk,
+                    value: ZIP_212_VALUE | rng.next_u32() as u8,
+                },
+                memo: (),
+                cv: (),
+            }
+        }
+
+        fn write_inputs(inputs: &TestVectorInputs<Self>, buf: &mut Vec<u8>) {
+            buf.push(inputs.ivk);
+        }
+
+        fn write_cmstar_bytes(cmstar_bytes: &MockCmx, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(&cmstar_bytes.0);
+        }
+    }
+
+    impl ShieldedOutput<IndexedDomain, ENC_CIPHERTEXT_SIZE> for TestVector<IndexedDomain> {
+        fn ephemeral_key(&self) -> EphemeralKeyBytes {
+            self.ephemeral_key
+        }
+
+        fn cmstar_bytes(&self) -> MockCmx {
+            self.cmstar_bytes
+        }
+
+        fn enc_ciphertext(&self) -> &[u8; ENC_CIPHERTEXT_SIZE] {
+            &self.enc_ciphertext
+        }
+    }
+
+    #[test]
+    fn generated_test_vectors_decrypt() {
+        let vectors = generate_test_vectors(&IndexedDomain, &mut MockRng(0), 10);
+        assert_eq!(vectors.len(), 10);
+
+        for vector in &vectors {
+            let inputs = &vector.inputs;
+            let expected = Some((inputs.note, inputs.ivk, ()));
+            assert_eq!(
+                try_note_decryption(&IndexedDomain, &inputs.ivk, vector),
+                expected
+            );
+            assert_eq!(
+                try_output_recovery_with_ovk(
+                    &IndexedDomain,
+                    &inputs.ovk,
+                    vector,
+                    &inputs.cv,
+                    &vector.out_ciphertext
+                ),
+                expected
+            );
+            assert_eq!(
+                vector.note_plaintext.0[..],
+                stages::decrypt_note_ciphertext::<IndexedDomain>(
+                    &IndexedDomain::kdf((), &vector.ephemeral_key),
+                    &vector.enc_ciphertext
+                )
+                .unwrap()
+                .0[..]
+            );
+
+            let mut buf = Vec::new();
+            vector.write(&mut buf);
+            let mut expected = vec![inputs.ivk];
+            expected.extend_from_slice(&vector.cmstar_bytes.0);
+            expected.extend_from_slice(&vector.ephemeral_key.0);
+            expected.extend_from_slice(&vector.note_plaintext.0);
+            expected.extend_from_slice(&vector.enc_ciphertext);
+            expected.extend_from_slice(&vector.out_ciphertext);
+            assert_eq!(buf, expected);
+        }
+
+        // The vectors are determined by the RNG.
+        let regenerated = generate_test_vectors(&IndexedDomain, &mut MockRng(0), 10);
+        for (a, b) in vectors.iter().zip(&regenerated) {
+            let (mut buf_a, mut buf_b) = (Vec::new(), Vec::new());
+            a.write(&mut buf_a);
+            b.write(&mut buf_b);
+            assert_eq!(buf_a, buf_b);
+        }
+    }
+}
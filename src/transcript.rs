@@ -0,0 +1,110 @@
+//! Transcripts of the intermediate values computed during trial decryption.
+//!
+//! **The APIs in this module expose secret values, and must never be used in
+//! production.** They are intended for protocol implementors debugging mismatches
+//! between implementations (for example between this crate, other implementations of the
+//! Zcash protocol, and the test vectors in the specification).
+
+use crate::consts::ENC_CIPHERTEXT_SIZE;
+use crate::{
+    decrypt_note_ciphertext, parse_and_validate_note_plaintext, Domain, EphemeralKeyBytes,
+    NotePlaintextBytes, ShieldedOutput,
+};
+
+/// Trait that exposes the encodings of secret domain values needed for transcripts.
+pub trait TranscriptDomain: Domain {
+    /// Returns the byte encoding of the given `SharedSecret`.
+    fn shared_secret_bytes(secret: &Self::SharedSecret) -> [u8; 32];
+}
+
+/// The intermediate values computed while trial-decrypting a single output.
+///
+/// Each field is `None` if trial decryption failed before the value was computed.
+pub struct DecryptionTranscript<D: Domain> {
+    /// The `ephemeral_key` field of the output.
+    pub ephemeral_key: EphemeralKeyBytes,
+    /// The byte encoding of the `SharedSecret` derived from the ephemeral key.
+    pub shared_secret: Option<[u8; 32]>,
+    /// The `SymmetricKey` derived from the shared secret.
+    pub key: Option<D::SymmetricKey>,
+    /// The note plaintext, if the note ciphertext was successfully decrypted.
+    pub plaintext: Option<NotePlaintextBytes>,
+    /// The result of trial decryption, as returned by [`crate::try_note_decryption`].
+    pub result: Option<(D::Note, D::Recipient, D::Memo)>,
+}
+
+/// Trial decryption of the full note plaintext by the recipient, recording a transcript
+/// of the intermediate values.
+///
+/// This performs the same steps as [`crate::try_note_decryption`]; the final result is
+/// available as [`DecryptionTranscript::result`].
+pub fn try_note_decryption_with_transcript<
+    D: TranscriptDomain,
+    Output: ShieldedOutput<D, ENC_CIPHERTEXT_SIZE>,
+>(
+    domain: &D,
+    ivk: &D::IncomingViewingKey,
+    output: &Output,
+) -> DecryptionTranscript<D> {
+    let mut transcript = DecryptionTranscript {
+        ephemeral_key: output.ephemeral_key(),
+        shared_secret: None,
+        key: None,
+        plaintext: None,
+        result: None,
+    };
+
+    let epk = match D::epk(&transcript.ephemeral_key) {
+        Some(epk) => D::prepare_epk(epk),
+        None => return transcript,
+    };
+    let shared_secret = D::ka_agree_dec(ivk, &epk);
+    transcript.shared_secret = Some(D::shared_secret_bytes(&shared_secret));
+    let key = D::kdf(shared_secret, &transcript.ephemeral_key);
+
+    transcript.plaintext = decrypt_note_ciphertext::<D>(&key, output.enc_ciphertext());
+    transcript.key = Some(key);
+
+    // Parse and validate the recorded plaintext, so that the result always matches it.
+    transcript.result = transcript.plaintext.as_ref().and_then(|plaintext| {
+        parse_and_validate_note_plaintext(domain, ivk, &transcript.ephemeral_key, output, plaintext)
+            .ok()
+    });
+
+    transcript
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::{try_note_decryption_with_transcript, TranscriptDomain};
+    use crate::test_domain::{full_output, MockCmx, MockDomain};
+    use crate::try_note_decryption;
+
+    impl TranscriptDomain for MockDomain {
+        fn shared_secret_bytes(_secret: &()) -> [u8; 32] {
+            [0; 32]
+        }
+    }
+
+    #[test]
+    fn transcript_matches_result() {
+        for (mask, value, cmx) in [
+            (0b01, 1, MockCmx::for_note(0, 1)),
+            (0b10, 2, MockCmx::for_note(1, 2)),
+            (0b01, 3, MockCmx::for_note(1, 3)),
+        ] {
+            let output = full_output(value, mask, value, cmx);
+            let transcript = try_note_decryption_with_transcript(&MockDomain, &0, &output);
+            assert_eq!(
+                transcript
+                    .plaintext
+                    .map(|plaintext| [plaintext.0[0], plaintext.0[1]]),
+                Some([mask, value])
+            );
+            assert_eq!(
+                transcript.result,
+                try_note_decryption(&MockDomain, &0, &output)
+            );
+        }
+    }
+}